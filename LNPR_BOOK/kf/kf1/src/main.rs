@@ -0,0 +1,46 @@
+use lnpr::prelude::*;
+use plotters::prelude::*;
+use std::f32::consts::PI;
+
+use ndarray::arr2;
+
+fn main() {
+    let mut map = Map::new();
+    for ln in &[(-4.0, 2.0), (2.0, -3.0), (3.0, 3.0)] {
+        map.append_landmark(*ln);
+    }
+
+    let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1);
+    let initial_pose = (2.0, 2.0, PI / 6.0);
+    let cov = arr2(&[
+        [0.18462f64.powi(2), 0.0, 0.0, 0.0],
+        [0.0, 0.001f64.powi(2), 0.0, 0.0],
+        [0.0, 0.0, 0.02264f64.powi(2), 0.0],
+        [0.0, 0.0, 0.0, 0.018462f64.powi(2)],
+    ]);
+    let estimator = KalmanFilter::new(map.clone(), initial_pose, cov);
+    let circle = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, estimator);
+
+    let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0))
+        .set_noise(0.0, 0.0)
+        .set_bias(0.0, 0.0)
+        .set_phantom(0.0, (-5.0, 5.0), (-5.0, 5.0))
+        .set_oversight(0.0);
+
+    let robot = Robot::new(
+        (2.0, 2.0, PI / 6.0),
+        &RGBColor(100, 100, 100),
+        circle.clone(),
+        camera.clone(),
+    )
+    .set_noise(5.0, PI / 60.0)
+    .set_bias((0.1, 0.1))
+    .set_stuck(f32::INFINITY, 1e-100)
+    .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
+
+    world.push(Box::new(robot));
+    let root = BitMapBackend::gif("world.gif", (500, 500), 100)
+        .unwrap()
+        .into_drawing_area();
+    world.draw(&root);
+}
@@ -1,9 +1,18 @@
+use std::any::Any;
 use std::f32::consts::PI;
 
+use ndarray::{Array1, Array2};
 use plotters::coord::types::RangedCoordi32;
 use plotters::coord::Shift;
 use plotters::prelude::*;
 
+use crate::collision::*;
+use crate::geometry::*;
+use crate::log::Log;
+use crate::occupancy_grid::OccupancyGrid;
+use crate::plotters_ext::*;
+use crate::rng::SimRng;
+
 #[derive(Clone, Debug)]
 pub struct Agent {
     pub nu: f32,
@@ -11,30 +20,175 @@ pub struct Agent {
 }
 
 pub trait AgentTrait {
-    fn decision(&mut self, obs: &Vec<(f32, f32)>) -> (f32, f32);
+    fn decision(&mut self, obs: &Vec<Observation>) -> (f32, f32);
+
+    // What the estimator of the agent believes, when it carries one
+    fn estimate(&self) -> Option<Estimate> {
+        None
+    }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    // Landmarks found by the agent, when its estimator builds a map
+    fn estimated_map(&self) -> Option<Map> {
+        None
+    }
+
+    // Runs the estimator of the agent over a recorded log, see `Log::replay`
+    fn replay(&mut self, _log: &Log) -> Option<Vec<Estimate>> {
+        None
+    }
+
+    // Random number generator of the world the agent is put in
+    fn set_rng(&mut self, _rng: SimRng) {}
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     );
 }
 
 impl AgentTrait for Agent {
-    fn decision(&mut self, _obs: &Vec<(f32, f32)>) -> (f32, f32) {
+    fn decision(&mut self, _obs: &Vec<Observation>) -> (f32, f32) {
         (self.nu, self.omega)
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        _drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        _drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         _xlim: i32,
         _ylim: i32,
     ) {
     }
 }
 
+pub trait Estimator {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32);
+
+    fn observation_update(&mut self, observation: &[Observation]);
+
+    fn pose(&self) -> (f32, f32, f32);
+
+    // Covariance of (x, y, theta), for estimators which keep one
+    fn pose_cov(&self) -> Option<Array2<f64>> {
+        None
+    }
+
+    // Innovations of the last observation update, for Kalman filters
+    fn innovations(&self) -> Vec<Innovation> {
+        Vec::new()
+    }
+
+    // Estimated landmarks, for estimators which do SLAM
+    fn estimated_map(&self) -> Option<Map> {
+        None
+    }
+
+    // Random number generator of the world, for estimators which sample
+    fn set_rng(&mut self, _rng: SimRng) {}
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    );
+}
+
+// Difference between an observation and the one predicted by a Kalman filter,
+// with the covariance predicted for it
+#[derive(Clone, Debug)]
+pub struct Innovation {
+    pub value: Array1<f64>,
+    pub cov: Array2<f64>,
+}
+
+// Output of an estimator at one moment
+#[derive(Clone, Debug)]
+pub struct Estimate {
+    pub pose: (f32, f32, f32),
+    pub cov: Option<Array2<f64>>,
+    pub innovations: Vec<Innovation>,
+}
+
+impl Estimate {
+    pub fn new(pose: (f32, f32, f32)) -> Self {
+        Estimate {
+            pose,
+            cov: None,
+            innovations: Vec::new(),
+        }
+    }
+
+    pub fn of<E: Estimator>(estimator: &E) -> Self {
+        Estimate {
+            pose: estimator.pose(),
+            cov: estimator.pose_cov(),
+            innovations: estimator.innovations(),
+        }
+    }
+}
+
+// Agent which moves with a fixed velocity and keeps its estimator up to date
+#[derive(Clone)]
+pub struct EstimateAgent<E: Estimator> {
+    pub nu: f32,
+    pub omega: f32,
+    pub time_interval: f32,
+    pub estimator: E,
+    prev_nu: f32,
+    prev_omega: f32,
+}
+
+impl<E: Estimator> EstimateAgent<E> {
+    pub fn new(nu: f32, omega: f32, time_interval: f32, estimator: E) -> Self {
+        EstimateAgent {
+            nu,
+            omega,
+            time_interval,
+            estimator,
+            prev_nu: 0.0,
+            prev_omega: 0.0,
+        }
+    }
+}
+
+impl<E: Estimator> AgentTrait for EstimateAgent<E> {
+    fn decision(&mut self, obs: &Vec<Observation>) -> (f32, f32) {
+        self.estimator
+            .motion_update(self.prev_nu, self.prev_omega, self.time_interval);
+        self.prev_nu = self.nu;
+        self.prev_omega = self.omega;
+        self.estimator.observation_update(obs);
+        (self.nu, self.omega)
+    }
+
+    fn estimate(&self) -> Option<Estimate> {
+        Some(Estimate::of(&self.estimator))
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        self.estimator.estimated_map()
+    }
+
+    fn replay(&mut self, log: &Log) -> Option<Vec<Estimate>> {
+        Some(log.replay_with(&mut self.estimator, Estimate::of))
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.estimator.set_rng(rng);
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        self.estimator.draw(drawing_area, xlim, ylim);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Landmark {
     pub position: (f32, f32),
@@ -42,9 +196,9 @@ pub struct Landmark {
 }
 
 impl Landmark {
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
@@ -64,24 +218,103 @@ impl Landmark {
     }
 }
 
+// Relative position (distance, direction) of a landmark seen from the camera.
+// `is_phantom` records that the reading was not produced by the landmark `id`;
+// it is ground truth for analysis, estimators must not rely on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Observation {
+    pub pos: (f32, f32),
+    pub id: i32,
+    pub is_phantom: bool,
+}
+
+impl Observation {
+    pub fn new(pos: (f32, f32), id: i32) -> Self {
+        Observation {
+            pos,
+            id,
+            is_phantom: false,
+        }
+    }
+}
+
+// Straight wall between two points
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wall {
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+}
+
+// Solid polygon, its vertices in order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Obstacle {
+    pub vertices: Vec<(f32, f32)>,
+}
+
+impl Obstacle {
+    // Edges of the outline, the last one closing it
+    pub fn edges(&self) -> impl Iterator<Item = ((f32, f32), (f32, f32))> + '_ {
+        let n = self.vertices.len();
+        (0..n).map(move |i| (self.vertices[i], self.vertices[(i + 1) % n]))
+    }
+
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        point_in_polygon(point, &self.vertices)
+    }
+}
+
 #[derive(Clone)]
 pub struct Map {
     pub landmarks: Vec<Landmark>,
+    pub walls: Vec<Wall>,
+    pub obstacles: Vec<Obstacle>,
 }
 
 impl Map {
     pub fn new() -> Self {
         Map {
             landmarks: Vec::new(),
+            walls: Vec::new(),
+            obstacles: Vec::new(),
         }
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        let to_pixel = |p: &(f32, f32)| translate_coord(drawing_area, p.0, p.1, xlim, ylim);
+        self.obstacles.iter().for_each(|o| {
+            let points: Vec<(i32, i32)> = o.vertices.iter().map(to_pixel).collect();
+            coord_spec
+                .draw(&Polygon::new(
+                    points.clone(),
+                    RGBColor(160, 160, 160).filled(),
+                ))
+                .unwrap();
+            coord_spec
+                .draw(&PathElement::new(
+                    points
+                        .iter()
+                        .chain(points.first())
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                    Into::<ShapeStyle>::into(&BLACK),
+                ))
+                .unwrap();
+        });
+        self.walls.iter().for_each(|w| {
+            coord_spec
+                .draw(&PathElement::new(
+                    vec![to_pixel(&w.from), to_pixel(&w.to)],
+                    ShapeStyle::from(&BLACK).stroke_width(3),
+                ))
+                .unwrap();
+        });
+
         self.landmarks
             .iter()
             .for_each(|l| l.clone().draw(drawing_area, xlim, ylim));
@@ -94,14 +327,75 @@ impl Map {
             id: id,
         });
     }
+
+    pub fn append_wall(&mut self, from: (f32, f32), to: (f32, f32)) {
+        self.walls.push(Wall { from, to });
+    }
+
+    pub fn append_obstacle(&mut self, vertices: Vec<(f32, f32)>) {
+        self.obstacles.push(Obstacle { vertices });
+    }
+
+    // Every wall and every edge of the obstacles
+    pub fn segments(&self) -> Vec<((f32, f32), (f32, f32))> {
+        self.walls
+            .iter()
+            .map(|w| (w.from, w.to))
+            .chain(self.obstacles.iter().flat_map(|o| o.edges()))
+            .collect()
+    }
+
+    // The segment from-to crosses a wall or an obstacle, or lies in an obstacle
+    pub fn intersects(&self, from: (f32, f32), to: (f32, f32)) -> bool {
+        self.segments()
+            .iter()
+            .any(|&(a, b)| segment_intersection(from, to, a, b).is_some())
+            || self.obstacles.iter().any(|o| o.contains(from))
+    }
+
+    pub fn inside_obstacle(&self, point: (f32, f32)) -> bool {
+        self.obstacles.iter().any(|o| o.contains(point))
+    }
+
+    // Distance from `origin` along the ray in `direction` to the first wall or
+    // obstacle within `max_range`
+    pub fn ray_cast(&self, origin: (f32, f32), direction: f32, max_range: f32) -> Option<f32> {
+        let end = (
+            origin.0 + max_range * direction.cos(),
+            origin.1 + max_range * direction.sin(),
+        );
+        self.segments()
+            .iter()
+            .filter_map(|&(a, b)| segment_intersection(origin, end, a, b))
+            .fold(None, |nearest: Option<f32>, t| {
+                Some(nearest.map_or(t, |n| n.min(t)))
+            })
+            .map(|t| t * max_range)
+    }
+
+    // Distance from `point` to the nearest wall or obstacle, zero inside an
+    // obstacle. None if the map has neither.
+    pub fn distance_to_nearest(&self, point: (f32, f32)) -> Option<f32> {
+        if self.inside_obstacle(point) {
+            return Some(0.0);
+        }
+        self.segments()
+            .iter()
+            .map(|&(a, b)| distance_to_segment(point, a, b))
+            .fold(None, |nearest: Option<f32>, d| {
+                Some(nearest.map_or(d, |n| n.min(d)))
+            })
+    }
 }
 
 #[derive(Clone)]
 pub struct IdealCamera {
     pub map: Map,
-    pub lastdata: Vec<(f32, f32)>,
+    pub lastdata: Vec<Observation>,
     pub distance_range: (f32, f32),
     pub direction_range: (f32, f32),
+    // bodies of the other robots, which hide what is behind them
+    pub obstructions: Vec<Footprint>,
 }
 
 impl IdealCamera {
@@ -111,14 +405,34 @@ impl IdealCamera {
             lastdata: Vec::new(),
             distance_range: distance_range,
             direction_range: direction_range,
+            obstructions: Vec::new(),
         }
     }
 }
 
+// Nothing of the map nor any of `obstructions` lies on the way from `from` to
+// `to`. Things just at `to`, like a landmark on a wall, do not hide it.
+pub fn in_line_of_sight(
+    map: &Map,
+    obstructions: &[Footprint],
+    from: (f32, f32),
+    to: (f32, f32),
+) -> bool {
+    let short = 1e-3;
+    let end = (
+        to.0 - (to.0 - from.0) * short,
+        to.1 - (to.1 - from.1) * short,
+    );
+    !map.intersects(from, end)
+        && obstructions
+            .iter()
+            .all(|o| distance_to_segment(o.position, from, end) >= o.radius)
+}
+
 pub trait OpticalSensor {
     fn map(&self) -> Map;
 
-    fn lastdata(&self) -> Vec<(f32, f32)>;
+    fn lastdata(&self) -> Vec<Observation>;
 
     fn distance_range(&self) -> (f32, f32);
 
@@ -131,30 +445,71 @@ pub trait OpticalSensor {
             && pos.1 <= self.direction_range().1
     }
 
-    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<(f32, f32)>;
+    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<Observation>;
+
+    // Landmarks in sight which were missed by the last `data`
+    fn overlooked(&self) -> Vec<Observation> {
+        Vec::new()
+    }
+
+    // Bodies of the other robots at the time of the next `data`, for sensors
+    // which cannot see through them
+    fn set_obstructions(&mut self, _obstructions: &[Footprint]) {}
+
+    // Random number generator of the world, for sensors with noise
+    fn set_rng(&mut self, _rng: SimRng) {}
 
     fn obs_fn(cam_pose: (f32, f32, f32), obj_pos: (f32, f32)) -> (f32, f32);
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
         cam_pose: (f32, f32, f32),
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
-        let (x, y, theta) = cam_pose;
-        let (x_, y_) = translate_coord(drawing_area, x, y, xlim, ylim);
+        draw_observations(&self.lastdata(), cam_pose, drawing_area, xlim, ylim);
+    }
+}
 
-        let coord_spec = drawing_area.strip_coord_spec();
-        self.lastdata().iter().for_each(|l| {
-            let (distance, direction) = l;
-            let lx = x + distance * (direction + theta).cos();
-            let ly = y + distance * (direction + theta).sin();
+// Lines from the sensor at `cam_pose` to what it observed
+pub fn draw_observations<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+    observations: &[Observation],
+    cam_pose: (f32, f32, f32),
+    drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+    xlim: i32,
+    ylim: i32,
+) {
+    let (x, y, theta) = cam_pose;
+    let (x_, y_) = translate_coord(drawing_area, x, y, xlim, ylim);
+
+    let coord_spec = drawing_area.strip_coord_spec();
+    observations.iter().for_each(|l| {
+        let (distance, direction) = l.pos;
+        let lx = x + distance * (direction + theta).cos();
+        let ly = y + distance * (direction + theta).sin();
+
+        let (lx_, ly_) = translate_coord(drawing_area, lx, ly, xlim, ylim);
+        draw_line(&coord_spec, (x_, y_), (lx_, ly_), &MAGENTA);
+    });
+}
 
-            let (lx_, ly_) = translate_coord(drawing_area, lx, ly, xlim, ylim);
-            draw_line(&coord_spec, (x_, y_), (lx_, ly_), &MAGENTA);
-        });
-    }
+// Shades the area `sensor` covers from `cam_pose`
+pub fn draw_field_of_view<DB: DrawingBackend, X: Ranged, Y: Ranged, OS: OpticalSensor>(
+    sensor: &OS,
+    cam_pose: (f32, f32, f32),
+    drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+    xlim: i32,
+    ylim: i32,
+) {
+    let fov = FieldOfView::new(
+        cam_pose,
+        sensor.distance_range(),
+        sensor.direction_range(),
+        YELLOW.mix(0.15).filled(),
+    )
+    .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+    drawing_area.strip_coord_spec().draw(&fov).unwrap();
 }
 
 impl OpticalSensor for IdealCamera {
@@ -162,7 +517,7 @@ impl OpticalSensor for IdealCamera {
         self.map.clone()
     }
 
-    fn lastdata(&self) -> Vec<(f32, f32)> {
+    fn lastdata(&self) -> Vec<Observation> {
         self.lastdata.clone()
     }
 
@@ -174,18 +529,30 @@ impl OpticalSensor for IdealCamera {
         self.direction_range
     }
 
-    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<(f32, f32)> {
+    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<Observation> {
         let observed = self
             .map
             .landmarks
             .iter()
-            .map(|l| Self::obs_fn(cam_pose, l.clone().position))
-            .filter(|pos| self.visible(*pos))
-            .collect::<Vec<(f32, f32)>>();
+            .filter(|l| {
+                in_line_of_sight(
+                    &self.map,
+                    &self.obstructions,
+                    (cam_pose.0, cam_pose.1),
+                    l.position,
+                )
+            })
+            .map(|l| Observation::new(Self::obs_fn(cam_pose, l.position), l.id))
+            .filter(|obs| self.visible(obs.pos))
+            .collect::<Vec<Observation>>();
         self.lastdata = observed;
         &self.lastdata
     }
 
+    fn set_obstructions(&mut self, obstructions: &[Footprint]) {
+        self.obstructions = obstructions.to_vec();
+    }
+
     fn obs_fn(cam_pose: (f32, f32, f32), obj_pos: (f32, f32)) -> (f32, f32) {
         let diff = (obj_pos.0 - cam_pose.0, obj_pos.1 - cam_pose.1);
         let mut phi = diff.1.atan2(diff.0) - cam_pose.2;
@@ -203,25 +570,32 @@ impl OpticalSensor for IdealCamera {
 }
 
 #[derive(Clone)]
-pub struct IdealRobot<'a, T: AgentTrait, U: OpticalSensor, C: Color> {
+pub struct IdealRobot<T: AgentTrait, U: OpticalSensor> {
     pub pose: (f32, f32, f32),
-    pub color: &'a C,
+    pub color: RGBAColor,
     pub agent: T,
     pub sensor: U,
     pub poses: Vec<(f32, f32, f32)>,
+    pub radius: f32,
 }
 
-impl<'a, T: AgentTrait, U: OpticalSensor, C: Color> IdealRobot<'a, T, U, C> {
-    pub fn new(pose: (f32, f32, f32), color: &'a C, agent: T, sensor: U) -> Self {
+impl<T: AgentTrait, U: OpticalSensor> IdealRobot<T, U> {
+    pub fn new<C: Color>(pose: (f32, f32, f32), color: &C, agent: T, sensor: U) -> Self {
         IdealRobot {
-            pose: pose,
-            color: color,
-            agent: agent,
-            sensor: sensor,
+            pose,
+            color: color.to_rgba(),
+            agent,
+            sensor,
             poses: vec![pose],
+            radius: ROBOT_RADIUS,
         }
     }
 
+    pub fn set_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
     pub fn state_transition(
         nu: f32,
         omega: f32,
@@ -245,88 +619,109 @@ impl<'a, T: AgentTrait, U: OpticalSensor, C: Color> IdealRobot<'a, T, U, C> {
     }
 }
 
-pub trait Robotize<'a, AT: AgentTrait, OS: OpticalSensor, C: 'a + Color> {
+// Anything the world moves and draws. The agent, sensor and color types are
+// hidden so that different kinds of robots can share one world.
+pub trait Robotize {
     fn pose(&self) -> (f32, f32, f32);
 
-    fn color(&self) -> &'a C;
-
-    fn sensor(&self) -> OS;
-
     fn poses(&self) -> Vec<(f32, f32, f32)>;
 
     fn append_poses(&mut self, pose: (f32, f32, f32));
 
-    fn agent(&self) -> AT;
-
     fn _state_transition(&mut self, nu: f32, omega: f32, time: f32);
 
     fn one_step(&mut self, time_interval: f32);
 
+    fn estimate(&self) -> Option<Estimate> {
+        None
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        None
+    }
+
+    // Everything the robot went through, for robots which record it
+    fn log(&self) -> Option<&Log> {
+        None
+    }
+
+    // Estimates of the agent over `log` instead of its own run
+    fn replay(&mut self, _log: &Log) -> Option<Vec<Estimate>> {
+        None
+    }
+
+    // Radius of the body, for collisions and drawing
+    fn radius(&self) -> f32 {
+        ROBOT_RADIUS
+    }
+
+    // Moves the robot to `pose` in place of where its last step took it
+    fn set_pose(&mut self, pose: (f32, f32, f32));
+
+    // Collisions at the end of the last step, for robots which record them
+    fn record_collisions(&mut self, _collisions: &[Collision]) {}
+
+    // Where the other robots are before the next step
+    fn set_others(&mut self, _others: &[Footprint]) {}
+
+    // Random number generator of the world the robot is pushed into, handed
+    // down to its agent and sensor. See `World::push`.
+    fn set_rng(&mut self, _rng: SimRng) {}
+
+    // Readings of the sensor at the start of the last step
+    fn lastdata(&self) -> Vec<Observation> {
+        Vec::new()
+    }
+
     fn draw(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<RangedCoordi32, RangedCoordi32>>,
+        drawing_area: &DrawingArea<DynBackend, Cartesian2d<RangedCoordi32, RangedCoordi32>>,
         xlim: i32,
         ylim: i32,
-    ) {
-        let pose = self.pose();
-        let (x, y) = translate_coord(drawing_area, pose.0, pose.1, xlim, ylim);
-        let round = 10.0;
-
-        let direction_x_end = x + (round * pose.2.cos()) as i32;
-        let direction_y_end = y + (round * -pose.2.sin()) as i32;
+    );
 
-        let coord_spec = drawing_area.strip_coord_spec();
-        draw_line(
-            &coord_spec,
-            (x, y),
-            (direction_x_end, direction_y_end),
-            self.color(),
-        );
+    // Gives back the concrete robot, see `World::object`
+    fn as_any(&self) -> &dyn Any;
+}
 
-        coord_spec
-            .draw(&Circle::new(
-                (x, y),
-                round as i32,
-                Into::<ShapeStyle>::into(self.color()),
-            ))
-            .unwrap();
+// Draws the robot body at the last pose, its sensor, its agent and the trail
+#[allow(clippy::too_many_arguments)]
+pub fn draw_robot<DB: DrawingBackend, X: Ranged, Y: Ranged, AT: AgentTrait, OS: OpticalSensor>(
+    drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+    xlim: i32,
+    ylim: i32,
+    color: &RGBAColor,
+    agent: &AT,
+    sensor: &OS,
+    poses: &[(f32, f32, f32)],
+    radius: f32,
+) {
+    // the sensor first, so that its field of view stays under the body
+    sensor.draw(poses[poses.len() - 2], drawing_area, xlim, ylim);
 
-        let poses = self.poses();
-        self.sensor()
-            .draw(poses[poses.len() - 2], drawing_area, xlim, ylim);
+    let pose = poses[poses.len() - 1];
+    let coord_spec = drawing_area.strip_coord_spec();
+    let body = RobotGlyph::new(pose, radius, Into::<ShapeStyle>::into(color))
+        .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+    coord_spec.draw(&body).unwrap();
 
-        self.agent().draw(drawing_area, xlim, ylim);
+    agent.draw(drawing_area, xlim, ylim);
 
-        for i in 1..poses.len() {
-            let from = poses[i - 1];
-            let to = poses[i];
-            let (fromx, fromy) = translate_coord(drawing_area, from.0, from.1, xlim, ylim);
-            let (tox, toy) = translate_coord(drawing_area, to.0, to.1, xlim, ylim);
+    for i in 1..poses.len() {
+        let from = poses[i - 1];
+        let to = poses[i];
+        let (fromx, fromy) = translate_coord(drawing_area, from.0, from.1, xlim, ylim);
+        let (tox, toy) = translate_coord(drawing_area, to.0, to.1, xlim, ylim);
 
-            draw_line(&coord_spec, (fromx, fromy), (tox, toy), &BLACK);
-        }
+        draw_line(&coord_spec, (fromx, fromy), (tox, toy), &BLACK);
     }
 }
 
-impl<'a, AT: AgentTrait + Clone, OS: OpticalSensor + Clone, C: Color> Robotize<'a, AT, OS, C>
-    for IdealRobot<'a, AT, OS, C>
-{
+impl<AT: AgentTrait + 'static, OS: OpticalSensor + 'static> Robotize for IdealRobot<AT, OS> {
     fn pose(&self) -> (f32, f32, f32) {
         self.pose
     }
 
-    fn color(&self) -> &'a C {
-        self.color.clone()
-    }
-
-    fn agent(&self) -> AT {
-        self.agent.clone()
-    }
-
-    fn sensor(&self) -> OS {
-        self.sensor.clone()
-    }
-
     fn poses(&self) -> Vec<(f32, f32, f32)> {
         self.poses.clone()
     }
@@ -345,18 +740,85 @@ impl<'a, AT: AgentTrait + Clone, OS: OpticalSensor + Clone, C: Color> Robotize<'
     fn _state_transition(&mut self, nu: f32, omega: f32, time: f32) {
         self.pose = Self::state_transition(nu, omega, time, self.pose);
     }
+
+    fn estimate(&self) -> Option<Estimate> {
+        self.agent.estimate()
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        self.agent.estimated_map()
+    }
+
+    fn replay(&mut self, log: &Log) -> Option<Vec<Estimate>> {
+        self.agent.replay(log)
+    }
+
+    fn draw(
+        &self,
+        drawing_area: &DrawingArea<DynBackend, Cartesian2d<RangedCoordi32, RangedCoordi32>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        draw_robot(
+            drawing_area,
+            xlim,
+            ylim,
+            &self.color,
+            &self.agent,
+            &self.sensor,
+            &self.poses,
+            self.radius,
+        );
+    }
+
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn set_pose(&mut self, pose: (f32, f32, f32)) {
+        self.pose = pose;
+        if let Some(last) = self.poses.last_mut() {
+            *last = pose;
+        }
+    }
+
+    fn set_others(&mut self, others: &[Footprint]) {
+        self.sensor.set_obstructions(others);
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.agent.set_rng(rng.clone());
+        self.sensor.set_rng(rng);
+    }
+
+    fn lastdata(&self) -> Vec<Observation> {
+        self.sensor.lastdata()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
-pub struct World<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> {
-    pub objects: Vec<Box<dyn Robotize<'a, AT, OS, C>>>,
+pub struct World {
+    // added with `push`, which hands them the generator of the world
+    objects: Vec<Box<dyn Robotize>>,
     pub map: Map,
     pub xlim: i32,
     pub ylim: i32,
     pub time_span: f32,
     pub time_interval: f32,
+    pub rng: SimRng,
+    // Robots go through everything if None
+    pub collision_response: Option<CollisionResponse>,
+    pub collisions: Vec<CollisionEvent>,
+    // Mapped from the readings of the `grid_robot`-th robot at its true poses
+    pub grid: Option<OccupancyGrid>,
+    pub grid_robot: usize,
+    steps: usize,
 }
 
-impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> World<'a, AT, OS, C> {
+impl World {
     pub fn new(map: Map, xlim: i32, ylim: i32, time_span: f32, time_interval: f32) -> Self {
         World {
             map: map,
@@ -365,47 +827,195 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> World<'a, AT, OS, C> {
             time_span: time_span,
             time_interval: time_interval,
             objects: Vec::new(),
+            rng: SimRng::default(),
+            collision_response: None,
+            collisions: Vec::new(),
+            grid: None,
+            grid_robot: 0,
+            steps: 0,
         }
     }
 
-    pub fn draw(&mut self, drawing_area: &DrawingArea<BitMapBackend, Shift>) {
-        let max_iteration = (self.time_span / self.time_interval) as i32;
-        for i in 0..max_iteration {
-            drawing_area.fill(&WHITE).unwrap();
+    // Builds `grid` from the scans of the robot of index `robot` as the world
+    // runs, and draws it under the map
+    pub fn set_grid(mut self, grid: OccupancyGrid, robot: usize) -> Self {
+        self.grid = Some(grid);
+        self.grid_robot = robot;
+        self
+    }
 
-            let mut chart = ChartBuilder::on(drawing_area)
-                .x_label_area_size(40)
-                .y_label_area_size(40)
-                .margin(5)
-                .build_cartesian_2d(-self.xlim..self.xlim, -self.ylim..self.ylim)
-                .unwrap();
+    // Keeps robots off the walls, the obstacles, the bounds of the world and
+    // each other
+    pub fn set_collision(mut self, response: CollisionResponse) -> Self {
+        self.collision_response = Some(response);
+        self
+    }
 
-            chart
-                .configure_mesh()
-                .disable_mesh()
-                .x_desc("X")
-                .y_desc("Y")
-                .axis_desc_style(("sans-serif", 15))
-                .draw()
-                .unwrap();
+    pub fn set_seed(self, seed: u64) -> Self {
+        self.rng.reseed(seed);
+        self
+    }
+
+    // Handle to the random number generator of the world
+    pub fn rng(&self) -> SimRng {
+        self.rng.clone()
+    }
+
+    // Adds `object` to the world, which draws all of its randomness, and that
+    // of its sensor and estimator, from the generator of the world from now on
+    pub fn push(&mut self, mut object: Box<dyn Robotize>) {
+        object.set_rng(self.rng());
+        self.objects.push(object);
+    }
+
+    pub fn objects(&self) -> &[Box<dyn Robotize>] {
+        &self.objects
+    }
+
+    pub fn objects_mut(&mut self) -> &mut [Box<dyn Robotize>] {
+        &mut self.objects
+    }
+
+    // The i-th object as the robot type it was pushed as, e.g.
+    // `world.object::<Robot<EstimateAgent<Mcl>, Camera>>(0)`. None when the
+    // index is out of range or the object is of another type.
+    pub fn object<R: 'static>(&self, i: usize) -> Option<&R> {
+        self.objects.get(i)?.as_any().downcast_ref::<R>()
+    }
+
+    pub fn draw<DB: DrawingBackend>(&mut self, drawing_area: &DrawingArea<DB, Shift>) {
+        for i in 0..self.max_iteration() {
+            self.draw_frame(i, drawing_area);
+        }
+    }
+
+    // Advances the world by one step and draws it as the i-th frame
+    pub fn draw_frame<DB: DrawingBackend>(
+        &mut self,
+        i: i32,
+        drawing_area: &DrawingArea<DB, Shift>,
+    ) {
+        drawing_area.fill(&WHITE).unwrap();
+
+        let mut chart = ChartBuilder::on(drawing_area)
+            .x_label_area_size(40)
+            .y_label_area_size(40)
+            .margin(5)
+            .build_cartesian_2d(-self.xlim..self.xlim, -self.ylim..self.ylim)
+            .unwrap();
 
-            let plotting_area = chart.plotting_area();
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_desc("X")
+            .y_desc("Y")
+            .axis_desc_style(("sans-serif", 15))
+            .draw()
+            .unwrap();
 
-            self.map.draw(&plotting_area, self.xlim, self.ylim);
+        let plotting_area = chart.plotting_area();
 
-            self.one_step((i as f32) * self.time_interval, &plotting_area);
-            for i in 0..self.objects.len() {
-                self.objects[i].draw(&plotting_area, self.xlim, self.ylim);
+        if let Some(grid) = self.grid.as_ref() {
+            grid.draw(plotting_area, self.xlim, self.ylim);
+        }
+        self.map.draw(plotting_area, self.xlim, self.ylim);
+
+        self.one_step((i as f32) * self.time_interval, plotting_area);
+
+        // Robots are drawn through `dyn Robotize`, which cannot be generic
+        // over the backend
+        let (xlim, ylim) = (self.xlim, self.ylim);
+        let objects = &self.objects;
+        draw_dyn(&plotting_area.strip_coord_spec(), |area| {
+            let area = area.apply_coord_spec(Cartesian2d::<RangedCoordi32, RangedCoordi32>::new(
+                -xlim..xlim,
+                -ylim..ylim,
+                area.get_pixel_range(),
+            ));
+            for object in objects.iter() {
+                object.draw(&area, xlim, ylim);
             }
+        });
+
+        drawing_area.present().unwrap();
+    }
+
+    pub fn max_iteration(&self) -> i32 {
+        (self.time_span / self.time_interval) as i32
+    }
+
+    // Runs the whole time span without any drawing backend
+    pub fn run(&mut self) {
+        for _ in 0..self.max_iteration() {
+            self.step();
+        }
+    }
 
-            drawing_area.present().unwrap();
+    // Advances every object by one time interval. Each robot moves after the
+    // ones before it, so it collides with where they already are.
+    pub fn step(&mut self) {
+        for i in 0..self.objects.len() {
+            let before = self.objects[i].pose();
+            let others = self.footprints_except(i);
+            self.objects[i].set_others(&others);
+            self.objects[i].one_step(self.time_interval);
+            if i == self.grid_robot {
+                if let Some(grid) = self.grid.as_mut() {
+                    grid.update(before, &self.objects[i].lastdata());
+                }
+            }
+            if let Some(response) = self.collision_response {
+                self.collide(i, before, response);
+            }
         }
+        self.steps += 1;
+    }
+
+    // Bodies of every robot but the i-th
+    fn footprints_except(&self, i: usize) -> Vec<Footprint> {
+        self.objects
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(j, o)| Footprint {
+                id: j,
+                position: (o.pose().0, o.pose().1),
+                radius: o.radius(),
+            })
+            .collect()
     }
 
-    fn one_step<X: Ranged, Y: Ranged>(
+    fn collide(&mut self, i: usize, before: (f32, f32, f32), response: CollisionResponse) {
+        let others = self.footprints_except(i);
+        let radius = self.objects[i].radius();
+        let contacts_at = |p| contacts(p, radius, &self.map, self.xlim, self.ylim, &others);
+
+        let after = self.objects[i].pose();
+        let touching = contacts_at((after.0, after.1));
+        if touching.is_empty() {
+            return;
+        }
+        let pose = respond(response, before, after, &touching, contacts_at);
+        let collisions: Vec<Collision> = distinct_contacts(&touching)
+            .into_iter()
+            .map(|contact| Collision { contact, pose })
+            .collect();
+
+        self.objects[i].set_pose(pose);
+        self.objects[i].record_collisions(&collisions);
+        let step = self.steps;
+        self.collisions
+            .extend(collisions.into_iter().map(|collision| CollisionEvent {
+                step,
+                robot: i,
+                collision,
+            }));
+    }
+
+    fn one_step<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &mut self,
         i: f32,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
     ) {
         let (x, y) = drawing_area.dim_in_pixel();
         let (xpos, ypos) = ((x as f32 / 10.0) as i32, (y as f32 / 10.0) as i32);
@@ -418,16 +1028,14 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> World<'a, AT, OS, C> {
             ))
             .unwrap();
 
-        for i in 0..self.objects.len() {
-            self.objects[i].one_step(self.time_interval);
-        }
+        self.step();
     }
 }
 
 type BackendCoord = (i32, i32);
 
-fn draw_line<C: Color>(
-    drawing_area: &DrawingArea<BitMapBackend, Shift>,
+fn draw_line<DB: DrawingBackend, C: Color>(
+    drawing_area: &DrawingArea<DB, Shift>,
     mut from: BackendCoord,
     mut to: BackendCoord,
     color: &C,
@@ -465,8 +1073,8 @@ fn draw_line<C: Color>(
     }
 }
 
-pub fn translate_coord<X: Ranged, Y: Ranged>(
-    drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+pub fn translate_coord<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+    drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
     x: f32,
     y: f32,
     xlim: i32,
@@ -486,6 +1094,17 @@ pub fn translate_coord<X: Ranged, Y: Ranged>(
     (translated_x, translated_y)
 }
 
+pub fn normalize_angle(mut theta: f32) -> f32 {
+    while theta >= PI {
+        theta -= 2.0 * PI;
+    }
+
+    while theta < -PI {
+        theta += 2.0 * PI;
+    }
+    theta
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -505,7 +1124,7 @@ mod tests {
             omega: 0.0,
         };
 
-        let obs = vec![(0.0, 0.0)];
+        let obs = vec![Observation::new((0.0, 0.0), 0)];
         assert_eq!(agent.decision(&obs), (0.2, 0.0));
     }
 
@@ -535,6 +1154,27 @@ mod tests {
         assert_eq!(camera.distance_range, (0.5, 4.0));
     }
 
+    #[test]
+    fn test_map_walls_and_obstacles() {
+        let mut map = Map::new();
+        map.append_wall((2.0, -1.0), (2.0, 1.0));
+        map.append_obstacle(vec![(-2.0, -1.0), (-1.0, -1.0), (-1.0, 1.0), (-2.0, 1.0)]);
+        assert_eq!(map.segments().len(), 5);
+
+        assert!(map.intersects((0.0, 0.0), (3.0, 0.0)));
+        assert!(!map.intersects((0.0, 0.0), (1.5, 0.0)));
+        assert!(map.intersects((-1.5, 0.0), (-1.4, 0.0)));
+        assert!(map.inside_obstacle((-1.5, 0.5)));
+
+        assert!((map.ray_cast((0.0, 0.0), 0.0, 5.0).unwrap() - 2.0).abs() < 1e-5);
+        assert!((map.ray_cast((0.0, 0.0), PI, 5.0).unwrap() - 1.0).abs() < 1e-5);
+        assert_eq!(map.ray_cast((0.0, 0.0), PI / 2.0, 5.0), None);
+
+        assert!((map.distance_to_nearest((0.0, 0.5)).unwrap() - 1.0).abs() < 1e-5);
+        assert_eq!(map.distance_to_nearest((-1.5, 0.0)), Some(0.0));
+        assert_eq!(Map::new().distance_to_nearest((0.0, 0.0)), None);
+    }
+
     #[test]
     fn test_ideal_camera_attributes() {
         let map = Map::new();
@@ -574,7 +1214,46 @@ mod tests {
         camera = IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6));
         lastdata = camera.data((0.0, 3.0, 0.0));
         assert_eq!(lastdata.len(), 1);
-        assert_eq!(lastdata.get(0), Some(&(2.0, 0.0)));
+        assert_eq!(lastdata.get(0), Some(&Observation::new((2.0, 0.0), 0)));
+    }
+
+    #[test]
+    fn test_cameras_do_not_see_through_walls_and_robots() {
+        let mut map = Map::new();
+        map.append_wall((1.0, -0.5), (1.0, 0.5));
+        map.append_landmark((2.0, 0.0));
+        map.append_landmark((2.0, 1.1));
+        map.append_landmark((1.0, 0.0));
+        let robot = [Footprint {
+            id: 1,
+            position: (1.5, 0.825),
+            radius: 0.2,
+        }];
+        let ids = |data: &Vec<Observation>| data.iter().map(|o| o.id).collect::<Vec<i32>>();
+
+        let mut ideal = IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6));
+        assert_eq!(ids(ideal.data((0.0, 0.0, 0.0))), vec![1, 2]);
+        ideal.set_obstructions(&robot);
+        assert_eq!(ids(ideal.data((0.0, 0.0, 0.0))), vec![2]);
+
+        let mut camera = crate::robot::Camera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6))
+            .set_rng(SimRng::seed_from_u64(1))
+            .set_noise(0.0, 0.0)
+            .set_bias(0.0, 0.0)
+            .set_oversight(0.0);
+        assert_eq!(ids(camera.data((0.0, 0.0, 0.0))), vec![1, 2]);
+        camera.set_obstructions(&robot);
+        assert_eq!(ids(camera.data((0.0, 0.0, 0.0))), vec![2]);
+        // hidden landmarks are not counted as overlooked
+        assert!(camera.overlooked().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_angle() {
+        assert_eq!(normalize_angle(0.5), 0.5);
+        assert!((normalize_angle(1.5 * PI) + 0.5 * PI).abs() < 1e-5);
+        assert!((normalize_angle(-1.5 * PI) - 0.5 * PI).abs() < 1e-5);
+        assert_eq!(normalize_angle(PI), -PI);
     }
 
     #[test]
@@ -618,7 +1297,108 @@ mod tests {
 
         let robot = IdealRobot::new((-2.0, 3.0, 0.0), &BLACK, agent, camera);
         let mut world = World::new(map.clone(), 5, 5, 10.0, 1.0);
-        world.objects.push(Box::new(robot));
+        world.push(Box::new(robot));
+    }
+
+    #[test]
+    fn test_run_world_headless() {
+        let map = Map::new();
+        let camera = IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6));
+        let agent = Agent {
+            nu: 0.2,
+            omega: 0.0,
+        };
+
+        let robot = IdealRobot::new((-2.0, 3.0, 0.0), &BLACK, agent, camera);
+        let mut world = World::new(map.clone(), 5, 5, 10.0, 1.0);
+        world.push(Box::new(robot));
+
+        world.step();
+        assert_eq!(world.objects()[0].poses().len(), 2);
+
+        world.run();
+        assert_eq!(world.objects()[0].poses().len(), 12);
+        assert!((world.objects()[0].pose().0 - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_robot_hides_landmark_from_another() {
+        let mut map = Map::new();
+        map.append_landmark((3.0, 0.0));
+        let camera = IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6));
+        let still = || Agent {
+            nu: 0.0,
+            omega: 0.0,
+        };
+        let mut world = World::new(map, 5, 5, 1.0, 1.0);
+        world.push(Box::new(IdealRobot::new(
+            (0.0, 0.0, 0.0),
+            &BLACK,
+            still(),
+            camera.clone(),
+        )));
+        world.push(Box::new(IdealRobot::new(
+            (1.5, 0.0, 0.0),
+            &RED,
+            still(),
+            camera,
+        )));
+        world.step();
+
+        let behind = world.object::<IdealRobot<Agent, IdealCamera>>(0).unwrap();
+        assert!(behind.sensor.lastdata.is_empty());
+        let front = world.object::<IdealRobot<Agent, IdealCamera>>(1).unwrap();
+        assert_eq!(front.sensor.lastdata.len(), 1);
+    }
+
+    #[test]
+    fn test_world_with_different_robots() {
+        let mut map = Map::new();
+        map.append_landmark((2.0, 0.0));
+        let agent = Agent {
+            nu: 0.2,
+            omega: 0.0,
+        };
+        let estimator = crate::kf::KalmanFilter::new(
+            map.clone(),
+            (0.0, 0.0, 0.0),
+            ndarray::Array2::from_diag(&ndarray::arr1(&[0.01, 1e-6, 0.01, 0.01])),
+        );
+
+        let ideal = IdealRobot::new(
+            (0.0, 0.0, 0.0),
+            &BLACK,
+            agent.clone(),
+            IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6)),
+        );
+        let estimating = IdealRobot::new(
+            (0.0, 0.0, 0.0),
+            &RGBColor(100, 100, 100),
+            EstimateAgent::new(0.2, 0.0, 1.0, estimator),
+            IdealCamera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6)),
+        );
+        let noisy = crate::robot::Robot::new(
+            (0.0, 0.0, 0.0),
+            &RED.mix(0.5),
+            agent,
+            crate::robot::Camera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6)),
+        );
+
+        let mut world = World::new(map.clone(), 5, 5, 3.0, 1.0);
+        world.push(Box::new(ideal));
+        world.push(Box::new(estimating));
+        world.push(Box::new(noisy));
+        world.run();
+
+        assert!(world.objects().iter().all(|o| o.poses().len() == 4));
+        assert!(world.object::<IdealRobot<Agent, IdealCamera>>(0).is_some());
+        assert!(world.object::<IdealRobot<Agent, IdealCamera>>(1).is_none());
+        assert!(world.object::<IdealRobot<Agent, IdealCamera>>(3).is_none());
+
+        let robot = world
+            .object::<IdealRobot<EstimateAgent<crate::kf::KalmanFilter>, IdealCamera>>(1)
+            .unwrap();
+        assert!((robot.agent.estimator.pose().0 - 0.4).abs() < 1e-3);
     }
 
     #[test]
@@ -644,12 +1424,76 @@ mod tests {
         let robot2 = IdealRobot::new((-2.0, -1.0, PI / 5.0 * 6.0), &RED, circle, camera.clone());
 
         let mut world = World::new(map.clone(), 5, 5, 10.0, 1.0);
-        world.objects.push(Box::new(robot1));
-        world.objects.push(Box::new(robot2));
+        world.push(Box::new(robot1));
+        world.push(Box::new(robot2));
 
         let root = BitMapBackend::gif("world.gif", (500, 500), 1000)
             .unwrap()
             .into_drawing_area();
         world.draw(&root);
     }
+
+    #[test]
+    fn test_draw_frame_on_other_backends() {
+        let mut map = Map::new();
+        map.append_landmark((2.0, -2.0));
+        let camera = IdealCamera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0));
+        let agent = Agent {
+            nu: 0.2,
+            omega: 0.0,
+        };
+        let robot = IdealRobot::new((0.0, 0.0, 0.0), &RED, agent, camera);
+        let mut world = World::new(map.clone(), 5, 5, 10.0, 1.0);
+        world.push(Box::new(robot));
+
+        let mut buffer = vec![0u8; 200 * 200 * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut buffer, (200, 200)).into_drawing_area();
+            world.draw_frame(0, &root);
+        }
+        // the robot is the only red thing in the picture
+        let red = buffer
+            .chunks(3)
+            .filter(|p| p[0] > 200 && p[1] < 100 && p[2] < 100)
+            .count();
+        assert!(red > 0);
+
+        let mut svg = String::new();
+        {
+            let root = SVGBackend::with_string(&mut svg, (200, 200)).into_drawing_area();
+            world.draw_frame(1, &root);
+        }
+        assert!(svg.contains("<polyline"));
+        assert!(svg.contains("#FF0000"));
+        assert_eq!(world.objects()[0].poses().len(), 3);
+    }
+
+    #[test]
+    fn test_draw_robot_body_and_field_of_view() {
+        let map = Map::new();
+        let camera = crate::robot::Camera::new(map.clone(), (0.5, 4.0), (-0.5, 0.5));
+        let agent = Agent {
+            nu: 0.0,
+            omega: 0.0,
+        };
+        let mut buffer = vec![255u8; 100 * 100 * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut buffer, (100, 100)).into_drawing_area();
+            let area = root.apply_coord_spec(Cartesian2d::<RangedCoordi32, RangedCoordi32>::new(
+                -5..5,
+                -5..5,
+                (0..100, 0..100),
+            ));
+            let poses = [(-3.0, 0.0, 0.0), (-3.0, 0.0, 0.0)];
+            draw_robot(&area, 5, 5, &RED.to_rgba(), &agent, &camera, &poses, 1.0);
+            root.present().unwrap();
+        }
+        let pixel = |x: usize, y: usize| &buffer[(y * 100 + x) * 3..(y * 100 + x) * 3 + 3];
+        // the outline and the front line of the body
+        assert_eq!(pixel(10, 50), &[255, 0, 0]);
+        assert_eq!(pixel(25, 50), &[255, 0, 0]);
+        // the camera covers the front of the robot but not its back
+        assert_ne!(pixel(45, 50), &[255, 255, 255]);
+        assert_eq!(pixel(30, 60), &[255, 255, 255]);
+    }
 }
@@ -0,0 +1,408 @@
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::base::*;
+use crate::rng::SimRng;
+
+// Beam measurement model of a range finder: a reading is a mixture of a hit
+// around the expected distance, a short reading from something unexpected in
+// front, a max range reading from a missed echo and a uniformly random one.
+// Distances are in meters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BeamModel {
+    pub z_hit: f32,
+    pub z_short: f32,
+    pub z_max: f32,
+    pub z_rand: f32,
+    // offset and spread of the hits from the expected distance
+    pub hit_bias: f32,
+    pub hit_std: f32,
+    // rate of the exponential short readings
+    pub short_rate: f32,
+    pub max_range: f32,
+}
+
+impl BeamModel {
+    pub fn new(max_range: f32) -> Self {
+        BeamModel {
+            z_hit: 0.8,
+            z_short: 0.1,
+            z_max: 0.05,
+            z_rand: 0.05,
+            hit_bias: 0.0,
+            hit_std: 0.02,
+            short_rate: 1.0,
+            max_range,
+        }
+    }
+
+    pub fn set_weights(mut self, z_hit: f32, z_short: f32, z_max: f32, z_rand: f32) -> Self {
+        let sum = z_hit + z_short + z_max + z_rand;
+        self.z_hit = z_hit / sum;
+        self.z_short = z_short / sum;
+        self.z_max = z_max / sum;
+        self.z_rand = z_rand / sum;
+        self
+    }
+
+    pub fn set_hit(mut self, hit_bias: f32, hit_std: f32) -> Self {
+        self.hit_bias = hit_bias;
+        self.hit_std = hit_std;
+        self
+    }
+
+    pub fn set_short_rate(mut self, short_rate: f32) -> Self {
+        self.short_rate = short_rate;
+        self
+    }
+
+    // Densities of the four parts at the reading `z` for the distance
+    // `expected`: hit, short, max, random. The hit is not renormalized to
+    // the range of the sensor.
+    fn components(&self, z: f32, expected: f32) -> [f64; 4] {
+        let (z, expected) = (z as f64, expected.min(self.max_range) as f64);
+        let max_range = self.max_range as f64;
+        if z >= max_range {
+            return [0.0, 0.0, 1.0, 0.0];
+        }
+
+        let std = self.hit_std as f64;
+        let e = (z - expected - self.hit_bias as f64) / std;
+        let hit = (-0.5 * e * e).exp() / (std * (2.0 * PI as f64).sqrt());
+
+        let rate = self.short_rate as f64;
+        let short = if z <= expected && expected > 0.0 {
+            rate * (-rate * z).exp() / (1.0 - (-rate * expected).exp())
+        } else {
+            0.0
+        };
+
+        [hit, short, 0.0, 1.0 / max_range]
+    }
+
+    pub fn likelihood(&self, z: f32, expected: f32) -> f64 {
+        let c = self.components(z, expected);
+        self.z_hit as f64 * c[0]
+            + self.z_short as f64 * c[1]
+            + self.z_max as f64 * c[2]
+            + self.z_rand as f64 * c[3]
+    }
+
+    // A reading of a beam whose true distance is `expected`
+    pub fn sample(&self, expected: f32, rng: &mut SimRng) -> f32 {
+        let expected = expected.min(self.max_range);
+        let u: f32 = rng.gen();
+        if u < self.z_hit {
+            let z = Normal::new(expected + self.hit_bias, self.hit_std)
+                .unwrap()
+                .sample(rng);
+            z.clamp(0.0, self.max_range)
+        } else if u < self.z_hit + self.z_short {
+            // inverse of the exponential truncated at the expected distance
+            let v: f32 = rng.gen();
+            let tail = 1.0 - (-self.short_rate * expected).exp();
+            -(1.0 - v * tail).ln() / self.short_rate
+        } else if u < self.z_hit + self.z_short + self.z_max {
+            self.max_range
+        } else {
+            rng.gen_range(0.0..self.max_range)
+        }
+    }
+
+    // Sum of the log likelihoods of the readings of `scan` from `pose`, the
+    // distances expected by ray casting on `map`
+    pub fn scan_log_likelihood(
+        &self,
+        map: &Map,
+        pose: (f32, f32, f32),
+        scan: &[Observation],
+    ) -> f64 {
+        scan.iter()
+            .map(|obs| {
+                let (z, direction) = obs.pos;
+                let expected = map
+                    .ray_cast((pose.0, pose.1), pose.2 + direction, self.max_range)
+                    .unwrap_or(self.max_range);
+                (self.likelihood(z, expected) + 1e-300).ln()
+            })
+            .sum()
+    }
+
+    // Expectation maximization from (reading, expected distance) pairs,
+    // starting from `self`. Stops after `iterations` or once the weights
+    // change by less than 1e-6.
+    pub fn fit(&self, samples: &[(f32, f32)], iterations: usize) -> Self {
+        let mut model = *self;
+        if samples.is_empty() {
+            return model;
+        }
+        let n = samples.len() as f64;
+        for _ in 0..iterations {
+            let weights = [model.z_hit, model.z_short, model.z_max, model.z_rand];
+            let mut e_sum = [0.0f64; 4];
+            let mut hit_offset = 0.0f64;
+            let responsibilities: Vec<[f64; 4]> = samples
+                .iter()
+                .map(|&(z, expected)| {
+                    let c = model.components(z, expected);
+                    let mut e = [0.0f64; 4];
+                    for k in 0..4 {
+                        e[k] = weights[k] as f64 * c[k];
+                    }
+                    let total: f64 = e.iter().sum();
+                    if total > 0.0 {
+                        e.iter_mut().for_each(|v| *v /= total);
+                    }
+                    e
+                })
+                .collect();
+            for (e, &(z, expected)) in responsibilities.iter().zip(samples.iter()) {
+                for k in 0..4 {
+                    e_sum[k] += e[k];
+                }
+                hit_offset += e[0] * (z - expected) as f64;
+            }
+
+            let bias = if e_sum[0] > 0.0 {
+                hit_offset / e_sum[0]
+            } else {
+                model.hit_bias as f64
+            };
+            let variance = responsibilities
+                .iter()
+                .zip(samples.iter())
+                .map(|(e, &(z, expected))| e[0] * ((z - expected) as f64 - bias).powi(2))
+                .sum::<f64>();
+
+            let next = BeamModel {
+                z_hit: (e_sum[0] / n) as f32,
+                z_short: (e_sum[1] / n) as f32,
+                z_max: (e_sum[2] / n) as f32,
+                z_rand: (e_sum[3] / n) as f32,
+                hit_bias: bias as f32,
+                hit_std: if e_sum[0] > 0.0 {
+                    ((variance / e_sum[0]).sqrt() as f32).max(1e-4)
+                } else {
+                    model.hit_std
+                },
+                short_rate: Self::fit_short_rate(samples, &responsibilities, model.short_rate),
+                max_range: model.max_range,
+            };
+            let change = (next.z_hit - model.z_hit).abs()
+                + (next.z_short - model.z_short).abs()
+                + (next.z_max - model.z_max).abs()
+                + (next.z_rand - model.z_rand).abs();
+            model = next;
+            if change < 1e-6 {
+                break;
+            }
+        }
+        model
+    }
+
+    // Rate of the short readings, which are exponential but cut at the
+    // expected distance. The weighted mean of the readings must equal
+    // 1/rate - d e^(-rate d) / (1 - e^(-rate d)) averaged over the expected
+    // distances d; solved by fixed point iteration from `rate`.
+    fn fit_short_rate(samples: &[(f32, f32)], responsibilities: &[[f64; 4]], rate: f32) -> f32 {
+        let (mut weight, mut distance) = (0.0f64, 0.0f64);
+        for (e, &(z, _)) in responsibilities.iter().zip(samples.iter()) {
+            weight += e[1];
+            distance += e[1] * z as f64;
+        }
+        if weight <= 0.0 || distance <= 0.0 {
+            return rate;
+        }
+        let mut rate = rate as f64;
+        for _ in 0..20 {
+            let cut: f64 = responsibilities
+                .iter()
+                .zip(samples.iter())
+                .map(|(e, &(_, d))| {
+                    let d = d as f64;
+                    let tail = (-rate * d).exp();
+                    e[1] * d * tail / (1.0 - tail).max(1e-12)
+                })
+                .sum();
+            rate = weight / (distance + cut);
+        }
+        rate as f32
+    }
+}
+
+// Readings of the range finder placed at a fixed distance from a wall, as in
+// `sensor_data/sensor_data_<distance in mm>.txt`. The lines hold the date, the
+// time, the infrared reading and the lidar reading in mm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorData {
+    // distance to the wall in meters
+    pub expected: f32,
+    // lidar readings in meters
+    pub lidar: Vec<f32>,
+}
+
+impl SensorData {
+    // The distance to the wall is taken from the number in the file name
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SensorDataError> {
+        let path = path.as_ref();
+        let expected = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.rsplit('_').next())
+            .and_then(|s| s.parse::<f32>().ok())
+            .ok_or_else(|| SensorDataError::Name(path.display().to_string()))?;
+        let mut data: SensorData = std::fs::read_to_string(path)?.parse()?;
+        data.expected = expected / 1000.0;
+        Ok(data)
+    }
+
+    // Every `sensor_data_*.txt` of `dir` in the order of their names
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, SensorDataError> {
+        let mut paths = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with("sensor_data_") && n.ends_with(".txt"))
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.iter().map(Self::load).collect()
+    }
+
+    // (reading, expected distance) pairs for `BeamModel::fit`
+    pub fn samples(&self) -> Vec<(f32, f32)> {
+        self.lidar.iter().map(|&z| (z, self.expected)).collect()
+    }
+}
+
+impl FromStr for SensorData {
+    type Err = SensorDataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lidar = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let parse_error = |message: String| SensorDataError::Parse {
+                line: i + 1,
+                message,
+            };
+            if fields.len() != 4 {
+                return Err(parse_error(format!(
+                    "expected 4 values, found {}",
+                    fields.len()
+                )));
+            }
+            let z = fields[3]
+                .parse::<f32>()
+                .map_err(|_| parse_error(format!("bad value: {}", fields[3])))?;
+            lidar.push(z / 1000.0);
+        }
+        Ok(SensorData {
+            expected: 0.0,
+            lidar,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SensorDataError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+    Name(String),
+}
+
+impl fmt::Display for SensorDataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SensorDataError::Io(e) => write!(f, "cannot read sensor data: {}", e),
+            SensorDataError::Parse { line, message } => {
+                write!(f, "invalid sensor data at line {}: {}", line, message)
+            }
+            SensorDataError::Name(name) => {
+                write!(f, "no distance in the name of sensor data: {}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SensorDataError {}
+
+impl From<std::io::Error> for SensorDataError {
+    fn from(e: std::io::Error) -> Self {
+        SensorDataError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_recovers_sampled_model() {
+        let truth = BeamModel::new(5.0)
+            .set_weights(0.7, 0.15, 0.05, 0.1)
+            .set_hit(0.01, 0.03)
+            .set_short_rate(2.0);
+        let mut rng = SimRng::seed_from_u64(3);
+        let samples: Vec<(f32, f32)> = (0..20000)
+            .map(|i| {
+                let expected = 1.0 + (i % 3) as f32;
+                (truth.sample(expected, &mut rng), expected)
+            })
+            .collect();
+
+        let fitted = BeamModel::new(5.0).fit(&samples, 200);
+        assert!((fitted.z_hit - 0.7).abs() < 0.03);
+        assert!((fitted.z_short - 0.15).abs() < 0.03);
+        assert!((fitted.z_max - 0.05).abs() < 0.01);
+        assert!((fitted.z_rand - 0.1).abs() < 0.03);
+        assert!((fitted.hit_bias - 0.01).abs() < 0.005);
+        assert!((fitted.hit_std - 0.03).abs() < 0.005);
+        assert!((fitted.short_rate - 2.0).abs() < 0.5);
+
+        // the fitted model explains the samples better than the initial one
+        let log_likelihood = |m: &BeamModel| {
+            samples
+                .iter()
+                .map(|&(z, e)| m.likelihood(z, e).ln())
+                .sum::<f64>()
+        };
+        assert!(log_likelihood(&fitted) > log_likelihood(&BeamModel::new(5.0)));
+    }
+
+    #[test]
+    fn test_fit_recorded_sensor_data() {
+        let data = "20180122 095819 305 214\n20180122 095822 299 211\n".parse::<SensorData>();
+        assert_eq!(data.unwrap().lidar, vec![0.214, 0.211]);
+        let bad = "20180122 095819 305\n".parse::<SensorData>();
+        assert!(matches!(bad, Err(SensorDataError::Parse { line: 1, .. })));
+
+        let data = SensorData::load("../sensor_data/sensor_data_200.txt").unwrap();
+        assert_eq!(data.expected, 0.2);
+        let fitted = BeamModel::new(5.0).fit(&data.samples(), 100);
+        // nearly all hits, a little farther than the wall and a few mm apart
+        assert!(fitted.z_hit > 0.9);
+        assert!(fitted.z_max < 1e-3);
+        assert!(fitted.hit_bias > 0.0 && fitted.hit_bias < 0.03);
+        assert!(fitted.hit_std < 0.02);
+    }
+
+    #[test]
+    fn test_scan_log_likelihood() {
+        let mut map = Map::new();
+        map.append_wall((2.0, -2.0), (2.0, 2.0));
+        let model = BeamModel::new(5.0);
+        let scan = [Observation::new((2.0, 0.0), 0)];
+        let right = model.scan_log_likelihood(&map, (0.0, 0.0, 0.0), &scan);
+        let wrong = model.scan_log_likelihood(&map, (1.0, 0.0, 0.0), &scan);
+        assert!(right > wrong);
+    }
+}
@@ -0,0 +1,348 @@
+use lnpr::prelude::*;
+use plotters::prelude::*;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str =
+    "usage: lnpr-sim SCENARIO [--out DIR] [--render [--format gif|png|svg]] [--seed N] [--record]
+       lnpr-sim SCENARIO --replay LOG_DIR [--out DIR]
+       lnpr-sim SCENARIO --runs N [--seed N] [--out DIR]
+
+Runs the scenario and writes to DIR (default: out)
+  trajectory.csv  true poses of every robot
+  estimate.csv    estimated poses of every robot with an estimator
+  map.csv         final landmark estimates of SLAM robots
+  metrics_N.csv   per-step errors of the estimator of robot N
+  world.gif       animation of the run (only with --render)
+  frames/         one frame_NNNN.png or .svg per step instead of world.gif
+                  (only with --render --format png or svg)
+  log_N.txt       everything robot N went through (only with --record)
+  collisions.csv  what the robots ran into (only with collision in [world])
+
+With --replay the estimators of the scenario are run over the log_N.txt files
+of LOG_DIR instead of simulating the world again.
+
+With --runs the scenario is simulated N times in parallel with the seeds
+N, N + 1, ... of --seed (default 0), and the final poses and errors of every
+robot in every run are written to runs.csv.";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Gif,
+    Png,
+    Svg,
+}
+
+struct Args {
+    scenario: PathBuf,
+    out: PathBuf,
+    render: bool,
+    format: Format,
+    seed: Option<u64>,
+    record: bool,
+    replay: Option<PathBuf>,
+    runs: Option<usize>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut scenario = None;
+    let mut out = PathBuf::from("out");
+    let mut render = false;
+    let mut format = Format::Gif;
+    let mut seed = None;
+    let mut record = false;
+    let mut replay = None;
+    let mut runs = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => out = args.next().ok_or("--out needs a directory")?.into(),
+            "--render" => render = true,
+            "--format" => {
+                format = match args.next().ok_or("--format needs a format")?.as_str() {
+                    "gif" => Format::Gif,
+                    "png" => Format::Png,
+                    "svg" => Format::Svg,
+                    value => return Err(format!("bad format: {}", value)),
+                }
+            }
+            "--record" => record = true,
+            "--replay" => replay = Some(args.next().ok_or("--replay needs a directory")?.into()),
+            "--runs" => {
+                let value = args.next().ok_or("--runs needs a number")?;
+                runs = Some(value.parse().map_err(|_| format!("bad runs: {}", value))?);
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a number")?;
+                seed = Some(value.parse().map_err(|_| format!("bad seed: {}", value))?);
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ if scenario.is_none() => scenario = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(Args {
+        scenario: scenario.ok_or("no scenario given")?,
+        out,
+        render,
+        format,
+        seed,
+        record,
+        replay,
+        runs,
+    })
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {}", message);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match (&args.replay, args.runs) {
+        (Some(dir), _) => replay(&args, dir),
+        (None, Some(runs)) => run_many(&args, runs),
+        (None, None) => run(&args),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut scenario = Scenario::load(&args.scenario)?;
+    if args.seed.is_some() {
+        scenario.world.seed = args.seed;
+    }
+    let mut world = scenario.build();
+    fs::create_dir_all(&args.out)?;
+
+    // estimates[robot][step], empty for robots without an estimator
+    let mut estimates: Vec<Vec<Estimate>> = world
+        .objects()
+        .iter()
+        .map(|o| o.estimate().into_iter().collect())
+        .collect();
+
+    let gif = args.out.join("world.gif");
+    let root = if args.render && args.format == Format::Gif {
+        Some(BitMapBackend::gif(&gif, (500, 500), 100)?.into_drawing_area())
+    } else {
+        None
+    };
+    let frames = args.out.join("frames");
+    if args.render && args.format != Format::Gif {
+        fs::create_dir_all(&frames)?;
+    }
+    for i in 0..world.max_iteration() {
+        let frame = frames.join(format!("frame_{:04}", i));
+        match (&root, args.render, args.format) {
+            (Some(root), _, _) => world.draw_frame(i, root),
+            (None, true, Format::Png) => {
+                let path = frame.with_extension("png");
+                world.draw_frame(
+                    i,
+                    &BitMapBackend::new(&path, (500, 500)).into_drawing_area(),
+                );
+            }
+            (None, true, Format::Svg) => {
+                let path = frame.with_extension("svg");
+                world.draw_frame(i, &SVGBackend::new(&path, (500, 500)).into_drawing_area());
+            }
+            _ => world.step(),
+        }
+        for (o, e) in world.objects().iter().zip(estimates.iter_mut()) {
+            e.extend(o.estimate());
+        }
+    }
+
+    let poses: Vec<_> = world.objects().iter().map(|o| o.poses()).collect();
+    write_results(&args.out, &world, &poses, &estimates)?;
+    if world.collision_response.is_some() {
+        write_collisions(&args.out.join("collisions.csv"), &world.collisions)?;
+        println!("{} collisions", world.collisions.len());
+    }
+    if args.record {
+        for (robot, o) in world.objects().iter().enumerate() {
+            if let Some(log) = o.log() {
+                log.save(args.out.join(format!("log_{}.txt", robot)))?;
+            }
+        }
+    }
+
+    println!("wrote results to {}", args.out.display());
+    Ok(())
+}
+
+fn replay(args: &Args, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let scenario = Scenario::load(&args.scenario)?;
+    let mut world = scenario.build();
+    fs::create_dir_all(&args.out)?;
+
+    let mut poses = Vec::new();
+    let mut estimates = Vec::new();
+    for (robot, o) in world.objects_mut().iter_mut().enumerate() {
+        let path = dir.join(format!("log_{}.txt", robot));
+        if !path.exists() {
+            poses.push(Vec::new());
+            estimates.push(Vec::new());
+            continue;
+        }
+        let log = Log::load(&path)?;
+        poses.push(log.poses());
+        estimates.push(o.replay(&log).unwrap_or_default());
+    }
+
+    write_results(&args.out, &world, &poses, &estimates)?;
+
+    println!("wrote results to {}", args.out.display());
+    Ok(())
+}
+
+fn run_many(args: &Args, runs: usize) -> Result<(), Box<dyn Error>> {
+    let scenario = Scenario::load(&args.scenario)?;
+    fs::create_dir_all(&args.out)?;
+
+    let experiment = Experiment::new(runs, |seed| {
+        let mut scenario = scenario.clone();
+        scenario.world.seed = Some(seed);
+        scenario.build()
+    })
+    .set_seed(args.seed.unwrap_or(0));
+    let results = experiment.results();
+
+    let failures = results.iter().filter(|r| r.failed).count();
+    println!("{}", to_dataframe(&results)?);
+    println!("{} failures in {} runs", failures, runs);
+    write_runs(&args.out.join("runs.csv"), &results)?;
+
+    println!("wrote results to {}", args.out.display());
+    Ok(())
+}
+
+fn write_results(
+    out: &Path,
+    world: &World,
+    poses: &[Vec<(f32, f32, f32)>],
+    estimates: &[Vec<Estimate>],
+) -> Result<(), Box<dyn Error>> {
+    let time_interval = world.time_interval;
+    let estimated_poses: Vec<Vec<_>> = estimates
+        .iter()
+        .map(|es| es.iter().map(|e| e.pose).collect())
+        .collect();
+    write_poses(&out.join("trajectory.csv"), poses, time_interval)?;
+    write_poses(&out.join("estimate.csv"), &estimated_poses, time_interval)?;
+    write_maps(&out.join("map.csv"), world)?;
+
+    for (robot, (truth, es)) in poses.iter().zip(estimates.iter()).enumerate() {
+        if es.is_empty() {
+            continue;
+        }
+        let evaluation = Evaluation::new(truth, es, 1);
+        evaluation.write_csv(out.join(format!("metrics_{}.csv", robot)))?;
+        println!("robot {}: {}", robot, evaluation.summary);
+    }
+    Ok(())
+}
+
+fn write_poses(
+    path: &Path,
+    poses: &[Vec<(f32, f32, f32)>],
+    time_interval: f32,
+) -> Result<(), Box<dyn Error>> {
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(f, "robot,step,time,x,y,theta")?;
+    for (robot, ps) in poses.iter().enumerate() {
+        for (step, p) in ps.iter().enumerate() {
+            let time = step as f32 * time_interval;
+            writeln!(f, "{},{},{},{},{},{}", robot, step, time, p.0, p.1, p.2)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_runs(path: &Path, results: &[RunResult]) -> Result<(), Box<dyn Error>> {
+    let optional = |v: Option<String>| v.unwrap_or_default();
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(
+        f,
+        "seed,robot,x,y,theta,estimate_x,estimate_y,estimate_theta,\
+         ate_rmse,heading_rmse,rpe_translation_rmse,mean_nees,mean_nis,failed"
+    )?;
+    for r in results.iter() {
+        let (x, y, theta) = r.pose;
+        let (ex, ey, etheta) = match r.estimate {
+            Some(e) => (e.0.to_string(), e.1.to_string(), e.2.to_string()),
+            None => Default::default(),
+        };
+        let s = r.summary.as_ref();
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            r.seed,
+            r.robot,
+            x,
+            y,
+            theta,
+            ex,
+            ey,
+            etheta,
+            optional(s.map(|s| s.ate_rmse.to_string())),
+            optional(s.map(|s| s.heading_rmse.to_string())),
+            optional(
+                s.and_then(|s| s.rpe_translation_rmse)
+                    .map(|v| v.to_string())
+            ),
+            optional(s.and_then(|s| s.mean_nees).map(|v| v.to_string())),
+            optional(s.and_then(|s| s.mean_nis).map(|v| v.to_string())),
+            r.failed
+        )?;
+    }
+    Ok(())
+}
+
+fn write_collisions(path: &Path, collisions: &[CollisionEvent]) -> Result<(), Box<dyn Error>> {
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(f, "step,robot,contact,other,x,y,theta")?;
+    for e in collisions.iter() {
+        let (contact, other) = match e.collision.contact {
+            Contact::Obstacle => ("obstacle", String::new()),
+            Contact::Bound => ("bound", String::new()),
+            Contact::Robot(id) => ("robot", id.to_string()),
+        };
+        let (x, y, theta) = e.collision.pose;
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{}",
+            e.step, e.robot, contact, other, x, y, theta
+        )?;
+    }
+    Ok(())
+}
+
+fn write_maps(path: &Path, world: &World) -> Result<(), Box<dyn Error>> {
+    let mut f = BufWriter::new(File::create(path)?);
+    writeln!(f, "robot,id,x,y")?;
+    for (robot, o) in world.objects().iter().enumerate() {
+        if let Some(map) = o.estimated_map() {
+            for l in map.landmarks.iter() {
+                writeln!(f, "{},{},{},{}", robot, l.id, l.position.0, l.position.1)?;
+            }
+        }
+    }
+    Ok(())
+}
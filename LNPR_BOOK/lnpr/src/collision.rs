@@ -0,0 +1,329 @@
+use serde::Deserialize;
+
+use crate::base::*;
+use crate::geometry::*;
+
+// Body radius of the robots unless set otherwise
+pub const ROBOT_RADIUS: f32 = 0.2;
+
+// What a robot ran into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Contact {
+    // a wall or an obstacle of the map
+    Obstacle,
+    // the border of the world at xlim or ylim
+    Bound,
+    // the robot of this index in `World::objects`
+    Robot(usize),
+}
+
+// What the world does with a robot which ran into something
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionResponse {
+    // The robot stays where it was before the step and only turns
+    Stop,
+    // The part of the move going into the contact is dropped
+    Slide,
+    // The robot goes through; the collision is only recorded
+    Flag,
+}
+
+// A robot overlapping something at the end of a step, and the pose the
+// response left it at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Collision {
+    pub contact: Contact,
+    pub pose: (f32, f32, f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollisionEvent {
+    pub step: usize,
+    pub robot: usize,
+    pub collision: Collision,
+}
+
+// Body of another robot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Footprint {
+    pub id: usize,
+    pub position: (f32, f32),
+    pub radius: f32,
+}
+
+// One overlap: the unit normal points from the contact towards the body and
+// `depth` is how far the body has to go along it to get free
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Touch {
+    pub contact: Contact,
+    pub normal: (f32, f32),
+    pub depth: f32,
+}
+
+fn unit(v: (f32, f32), otherwise: (f32, f32)) -> (f32, f32) {
+    let norm = (v.0 * v.0 + v.1 * v.1).sqrt();
+    if norm < 1e-9 {
+        otherwise
+    } else {
+        (v.0 / norm, v.1 / norm)
+    }
+}
+
+// Everything a body of `radius` at `position` overlaps
+pub fn contacts(
+    position: (f32, f32),
+    radius: f32,
+    map: &Map,
+    xlim: i32,
+    ylim: i32,
+    others: &[Footprint],
+) -> Vec<Touch> {
+    let mut found = Vec::new();
+
+    let distance = |c: (f32, f32)| ((c.0 - position.0).powi(2) + (c.1 - position.1).powi(2)).sqrt();
+    let closest = map
+        .segments()
+        .into_iter()
+        .map(|(a, b)| closest_point_on_segment(position, a, b));
+    if map.inside_obstacle(position) {
+        // the way out is through the nearest edge
+        let nearest = closest.min_by(|p, q| distance(*p).partial_cmp(&distance(*q)).unwrap());
+        if let Some(c) = nearest {
+            found.push(Touch {
+                contact: Contact::Obstacle,
+                normal: unit((c.0 - position.0, c.1 - position.1), (1.0, 0.0)),
+                depth: distance(c) + radius,
+            });
+        }
+    } else {
+        for c in closest.filter(|c| distance(*c) < radius) {
+            found.push(Touch {
+                contact: Contact::Obstacle,
+                normal: unit((position.0 - c.0, position.1 - c.1), (1.0, 0.0)),
+                depth: radius - distance(c),
+            });
+        }
+    }
+
+    let (xlim, ylim) = (xlim as f32, ylim as f32);
+    let bounds = [
+        (position.0 + radius - xlim, (-1.0, 0.0)),
+        (-xlim - (position.0 - radius), (1.0, 0.0)),
+        (position.1 + radius - ylim, (0.0, -1.0)),
+        (-ylim - (position.1 - radius), (0.0, 1.0)),
+    ];
+    for &(depth, normal) in bounds.iter().filter(|b| b.0 > 0.0) {
+        found.push(Touch {
+            contact: Contact::Bound,
+            normal,
+            depth,
+        });
+    }
+
+    for other in others.iter() {
+        let d = distance(other.position);
+        let reach = radius + other.radius;
+        if d < reach {
+            let away = (position.0 - other.position.0, position.1 - other.position.1);
+            found.push(Touch {
+                contact: Contact::Robot(other.id),
+                normal: unit(away, (1.0, 0.0)),
+                depth: reach - d,
+            });
+        }
+    }
+    found
+}
+
+// Goes against the normal of one of `touching`
+fn moves_into(from: (f32, f32), to: (f32, f32), touching: &[Touch]) -> bool {
+    let d = (to.0 - from.0, to.1 - from.1);
+    touching
+        .iter()
+        .any(|t| d.0 * t.normal.0 + d.1 * t.normal.1 < 0.0)
+}
+
+// Pose of a robot which went from `before` to `after` and is `touching`
+// things there. `contacts_at` gives the contacts at other positions.
+// Moving out of an overlap is always allowed.
+pub fn respond<F: Fn((f32, f32)) -> Vec<Touch>>(
+    response: CollisionResponse,
+    before: (f32, f32, f32),
+    after: (f32, f32, f32),
+    touching: &[Touch],
+    contacts_at: F,
+) -> (f32, f32, f32) {
+    let (from, to) = ((before.0, before.1), (after.0, after.1));
+    let stopped = (before.0, before.1, after.2);
+    match response {
+        CollisionResponse::Flag => after,
+        _ if !moves_into(from, to, touching) => after,
+        CollisionResponse::Stop => stopped,
+        CollisionResponse::Slide => {
+            // keeps the move along the contacts, then steps back out of them
+            let mut d = (to.0 - from.0, to.1 - from.1);
+            for t in touching.iter() {
+                let dn = d.0 * t.normal.0 + d.1 * t.normal.1;
+                if dn < 0.0 {
+                    d = (d.0 - dn * t.normal.0, d.1 - dn * t.normal.1);
+                }
+            }
+            let mut slid = (from.0 + d.0, from.1 + d.1);
+            for t in contacts_at(slid).iter() {
+                let depth = t.depth + 1e-4;
+                slid = (slid.0 + depth * t.normal.0, slid.1 + depth * t.normal.1);
+            }
+            if contacts_at(slid).is_empty() {
+                (slid.0, slid.1, after.2)
+            } else {
+                stopped
+            }
+        }
+    }
+}
+
+// Each contact once, in the order found
+pub fn distinct_contacts(touching: &[Touch]) -> Vec<Contact> {
+    let mut distinct: Vec<Contact> = Vec::new();
+    for t in touching.iter() {
+        if !distinct.contains(&t.contact) {
+            distinct.push(t.contact);
+        }
+    }
+    distinct
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::robot::*;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    fn robot(pose: (f32, f32, f32), nu: f32) -> IdealRobot<Agent, IdealCamera> {
+        let camera = IdealCamera::new(Map::new(), (0.5, 4.0), (-0.6, 0.6));
+        IdealRobot::new(pose, &BLACK, Agent { nu, omega: 0.0 }, camera)
+    }
+
+    #[test]
+    fn test_contacts() {
+        let mut map = Map::new();
+        map.append_wall((1.0, -1.0), (1.0, 1.0));
+        let others = [Footprint {
+            id: 3,
+            position: (0.0, 0.5),
+            radius: 0.2,
+        }];
+
+        let found = contacts((0.9, 0.0), 0.2, &map, 5, 5, &others);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].contact, Contact::Obstacle);
+        assert!((found[0].normal.0 + 1.0).abs() < 1e-5);
+        assert!((found[0].depth - 0.1).abs() < 1e-5);
+
+        let found = contacts((0.0, 0.2), 0.2, &map, 5, 5, &others);
+        assert_eq!(distinct_contacts(&found), vec![Contact::Robot(3)]);
+        let found = contacts((-4.9, 0.0), 0.2, &map, 5, 5, &others);
+        assert_eq!(distinct_contacts(&found), vec![Contact::Bound]);
+        assert_eq!(found[0].normal, (1.0, 0.0));
+        assert!(contacts((0.0, -1.0), 0.2, &map, 5, 5, &others).is_empty());
+    }
+
+    #[test]
+    fn test_responses() {
+        let mut map = Map::new();
+        map.append_wall((1.0, -1.0), (1.0, 1.0));
+        let at = |p| contacts(p, 0.2, &map, 5, 5, &[]);
+        let (before, after) = ((0.75, 0.0, 0.7), (0.85, 0.1, 0.8));
+        let touching = at((after.0, after.1));
+
+        let stop = respond(CollisionResponse::Stop, before, after, &touching, at);
+        assert_eq!(stop, (0.75, 0.0, 0.8));
+        let slide = respond(CollisionResponse::Slide, before, after, &touching, at);
+        assert!((slide.0 - 0.75).abs() < 1e-5 && (slide.1 - 0.1).abs() < 1e-5);
+        let flag = respond(CollisionResponse::Flag, before, after, &touching, at);
+        assert_eq!(flag, after);
+
+        // leaving the wall is not held back
+        let (inside, leaving) = ((0.9, 0.0, 0.0), (0.85, 0.0, 0.0));
+        let away = respond(
+            CollisionResponse::Stop,
+            inside,
+            leaving,
+            &at((0.85, 0.0)),
+            at,
+        );
+        assert_eq!(away, leaving);
+    }
+
+    #[test]
+    fn test_world_keeps_robots_apart() {
+        let mut map = Map::new();
+        map.append_wall((2.0, -5.0), (2.0, 5.0));
+        let mut world =
+            World::new(map.clone(), 5, 5, 5.0, 0.1).set_collision(CollisionResponse::Stop);
+        world.push(Box::new(robot((0.0, 0.0, 0.0), 1.0)));
+        world.push(Box::new(robot((-3.0, 1.0, PI), 1.0)));
+        let camera = Camera::new(map.clone(), (0.5, 4.0), (-0.6, 0.6));
+        let noisy = Robot::new(
+            (0.0, 2.0, PI),
+            &RED,
+            Agent {
+                nu: 1.0,
+                omega: 0.0,
+            },
+            camera,
+        )
+        .set_noise(0.0, 0.0)
+        .set_bias((0.0, 0.0))
+        .set_radius(0.3);
+        world.push(Box::new(noisy));
+        world.run();
+
+        // stopped at the wall and at the left bound
+        assert!((world.objects()[0].pose().0 - 1.8).abs() < 0.11);
+        assert!(world.objects()[1].pose().0 > -4.8 && world.objects()[1].pose().0 < -4.6);
+        assert!(world.objects()[2].pose().0 > -4.7);
+        assert!(world
+            .collisions
+            .iter()
+            .any(|e| e.robot == 0 && e.collision.contact == Contact::Obstacle));
+        assert!(world
+            .collisions
+            .iter()
+            .any(|e| e.robot == 2 && e.collision.contact == Contact::Bound));
+
+        let noisy = world.object::<Robot<Agent, Camera>>(2).unwrap();
+        let logged = noisy
+            .log
+            .steps
+            .iter()
+            .flat_map(|s| s.collisions.iter())
+            .count();
+        assert_eq!(
+            logged,
+            world.collisions.iter().filter(|e| e.robot == 2).count()
+        );
+        assert!(logged > 0);
+    }
+
+    #[test]
+    fn test_robots_do_not_overlap() {
+        let mut world =
+            World::new(Map::new(), 5, 5, 6.0, 0.1).set_collision(CollisionResponse::Slide);
+        world.push(Box::new(robot((-1.0, 0.0, 0.0), 0.5)));
+        world.push(Box::new(robot((1.0, 0.1, PI), 0.5)));
+        world.run();
+
+        let (a, b) = (world.objects()[0].pose(), world.objects()[1].pose());
+        let distance = ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        assert!(distance >= 0.4 - 1e-4);
+        assert!(world
+            .collisions
+            .iter()
+            .any(|e| e.collision.contact == Contact::Robot(1)));
+        // they slid past each other
+        assert!(a.0 > 1.0 && b.0 < -1.0);
+    }
+}
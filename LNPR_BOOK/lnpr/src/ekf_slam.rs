@@ -0,0 +1,320 @@
+use ndarray::{arr1, arr2, s, Array1, Array2};
+use ndarray_linalg::Inverse;
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::kf::*;
+use crate::plotters_ext::*;
+
+// Mean distance between the estimated and the true position of the
+// landmarks found so far. None until a landmark of `truth` is found.
+pub fn map_error(estimated: &Map, truth: &Map) -> Option<f32> {
+    let errors = truth
+        .landmarks
+        .iter()
+        .filter_map(|t| {
+            estimated.landmarks.iter().find(|l| l.id == t.id).map(|l| {
+                ((l.position.0 - t.position.0).powi(2) + (l.position.1 - t.position.1).powi(2))
+                    .sqrt()
+            })
+        })
+        .collect::<Vec<f32>>();
+    if errors.is_empty() {
+        None
+    } else {
+        Some(errors.iter().sum::<f32>() / errors.len() as f32)
+    }
+}
+
+// EKF-SLAM. The state is the robot pose followed by the position of every
+// landmark seen so far, in the order of `landmark_ids`.
+#[derive(Clone)]
+pub struct EkfSlam {
+    pub mean: Array1<f64>,
+    pub cov: Array2<f64>,
+    pub landmark_ids: Vec<i32>,
+    pub motion_noise_cov: Array2<f64>,
+    pub distance_dev_rate: f32,
+    pub direction_dev: f32,
+    pub innovations: Vec<Innovation>,
+}
+
+impl EkfSlam {
+    pub fn new(init_pose: (f32, f32, f32), motion_noise_cov: Array2<f64>) -> Self {
+        EkfSlam {
+            mean: arr1(&[init_pose.0 as f64, init_pose.1 as f64, init_pose.2 as f64]),
+            cov: Array2::from_diag(&arr1(&[1e-10, 1e-10, 1e-10])),
+            landmark_ids: Vec::new(),
+            motion_noise_cov,
+            distance_dev_rate: 0.14,
+            direction_dev: 0.05,
+            innovations: Vec::new(),
+        }
+    }
+
+    pub fn set_observation_noise(mut self, distance_dev_rate: f32, direction_dev: f32) -> Self {
+        self.distance_dev_rate = distance_dev_rate;
+        self.direction_dev = direction_dev;
+        self
+    }
+
+    // Index of the landmark in the state vector
+    fn landmark_index(&self, id: i32) -> Option<usize> {
+        self.landmark_ids
+            .iter()
+            .position(|i| *i == id)
+            .map(|i| 3 + 2 * i)
+    }
+
+    pub fn landmark_position(&self, id: i32) -> Option<(f32, f32)> {
+        self.landmark_index(id)
+            .map(|i| (self.mean[i] as f32, self.mean[i + 1] as f32))
+    }
+
+    pub fn landmark_cov(&self, id: i32) -> Option<Array2<f64>> {
+        self.landmark_index(id)
+            .map(|i| self.cov.slice(s![i..i + 2, i..i + 2]).to_owned())
+    }
+
+    // Estimated landmarks. Ids are the ones reported by the camera.
+    pub fn map(&self) -> Map {
+        let mut map = Map::new();
+        for id in self.landmark_ids.iter() {
+            map.landmarks.push(Landmark {
+                position: self.landmark_position(*id).unwrap(),
+                id: *id,
+            });
+        }
+        map
+    }
+
+    pub fn map_error(&self, truth: &Map) -> Option<f32> {
+        map_error(&self.map(), truth)
+    }
+
+    fn add_landmark(&mut self, obs: &Observation) {
+        let pose = self.pose();
+        let (ell, phi) = obs.pos;
+        let (s, c) = (pose.2 + phi).sin_cos();
+        let position = arr1(&[(pose.0 + ell * c) as f64, (pose.1 + ell * s) as f64]);
+        let (ell, s, c) = (ell as f64, s as f64, c as f64);
+
+        // Jacobians of the landmark position w.r.t. the pose and the observation
+        let gx = arr2(&[[1.0, 0.0, -ell * s], [0.0, 1.0, ell * c]]);
+        let gz = arr2(&[[c, -ell * s], [s, ell * c]]);
+        let q = mat_q(obs.pos.0 * self.distance_dev_rate, self.direction_dev);
+
+        let n = self.mean.len();
+        let cross = gx.dot(&self.cov.slice(s![0..3, ..]));
+        let landmark_cov =
+            gx.dot(&self.cov.slice(s![0..3, 0..3])).dot(&gx.t()) + gz.dot(&q).dot(&gz.t());
+
+        let mut mean = Array1::<f64>::zeros(n + 2);
+        mean.slice_mut(s![0..n]).assign(&self.mean);
+        mean.slice_mut(s![n..]).assign(&position);
+
+        let mut cov = Array2::<f64>::zeros((n + 2, n + 2));
+        cov.slice_mut(s![0..n, 0..n]).assign(&self.cov);
+        cov.slice_mut(s![n.., 0..n]).assign(&cross);
+        cov.slice_mut(s![0..n, n..]).assign(&cross.t());
+        cov.slice_mut(s![n.., n..]).assign(&landmark_cov);
+
+        self.mean = mean;
+        self.cov = cov;
+        self.landmark_ids.push(obs.id);
+    }
+}
+
+impl Estimator for EkfSlam {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        // The jacobians are singular at omega = 0
+        let omega = if omega.abs() < 1e-5 { 1e-5 } else { omega };
+        let pose = self.pose();
+        let m = mat_m(nu, omega, time, &self.motion_noise_cov);
+        let a = mat_a(nu, omega, time, pose.2);
+        let f = mat_f(nu, omega, time, pose.2);
+
+        let n = self.mean.len();
+        let mut full_f = Array2::<f64>::eye(n);
+        full_f.slice_mut(s![0..3, 0..3]).assign(&f);
+        let mut cov = full_f.dot(&self.cov).dot(&full_f.t());
+        let noise = a.dot(&m).dot(&a.t());
+        cov.slice_mut(s![0..3, 0..3])
+            .zip_mut_with(&noise, |c, n| *c += n);
+        self.cov = cov;
+
+        let pose = IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, pose);
+        self.mean[0] = pose.0 as f64;
+        self.mean[1] = pose.1 as f64;
+        self.mean[2] = normalize_angle(pose.2) as f64;
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        self.innovations.clear();
+        for obs in observation.iter() {
+            let index = match self.landmark_index(obs.id) {
+                Some(i) => i,
+                None => {
+                    self.add_landmark(obs);
+                    continue;
+                }
+            };
+
+            let pose = self.pose();
+            let landmark_pos = (self.mean[index] as f32, self.mean[index + 1] as f32);
+            let estimated = IdealCamera::obs_fn(pose, landmark_pos);
+            let hx = mat_h(pose, landmark_pos);
+
+            let n = self.mean.len();
+            let mut h = Array2::<f64>::zeros((2, n));
+            h.slice_mut(s![.., 0..3]).assign(&hx);
+            h.slice_mut(s![.., index..index + 2])
+                .assign(&(-&hx.slice(s![.., 0..2])));
+
+            let q = mat_q(estimated.0 * self.distance_dev_rate, self.direction_dev);
+            let s = q + h.dot(&self.cov).dot(&h.t());
+            // nothing to learn from an observation without any uncertainty
+            let s_inv = match s.inv() {
+                Ok(s_inv) => s_inv,
+                Err(_) => continue,
+            };
+            let k = self.cov.dot(&h.t()).dot(&s_inv);
+
+            let innovation = arr1(&[
+                (obs.pos.0 - estimated.0) as f64,
+                normalize_angle(obs.pos.1 - estimated.1) as f64,
+            ]);
+            self.mean = &self.mean + &k.dot(&innovation);
+            self.innovations.push(Innovation {
+                value: innovation,
+                cov: s,
+            });
+            self.mean[2] = normalize_angle(self.mean[2] as f32) as f64;
+            self.cov = (Array2::<f64>::eye(n) - k.dot(&h)).dot(&self.cov);
+        }
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        (
+            self.mean[0] as f32,
+            self.mean[1] as f32,
+            self.mean[2] as f32,
+        )
+    }
+
+    fn pose_cov(&self) -> Option<Array2<f64>> {
+        Some(self.cov.slice(s![0..3, 0..3]).to_owned())
+    }
+
+    fn innovations(&self) -> Vec<Innovation> {
+        self.innovations.clone()
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        Some(self.map())
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        let (x, y, t) = self.pose();
+        let from = translate_coord(drawing_area, x, y, xlim, ylim);
+        let to = (
+            from.0 + (20.0 * t.cos()) as i32,
+            from.1 + (20.0 * -t.sin()) as i32,
+        );
+        coord_spec
+            .draw(&Quiver::new(from, to, Into::<ShapeStyle>::into(&BLUE)))
+            .unwrap();
+        let ellipse = ErrorEllipse::new((x, y), &self.cov, 0.99, BLUE)
+            .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+        coord_spec.draw(&ellipse).unwrap();
+
+        self.map().landmarks.iter().for_each(|l| {
+            let pos = translate_coord(drawing_area, l.position.0, l.position.1, xlim, ylim);
+            coord_spec
+                .draw(&Cross::new(pos, 6, Into::<ShapeStyle>::into(&BLUE)))
+                .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::f32::consts::PI;
+
+    fn observe(map: &Map, pose: (f32, f32, f32)) -> Vec<Observation> {
+        map.landmarks
+            .iter()
+            .map(|l| Observation::new(IdealCamera::obs_fn(pose, l.position), l.id))
+            .collect()
+    }
+
+    #[test]
+    fn test_new_landmarks_augment_state() {
+        let truth = landmarks();
+        let mut slam = EkfSlam::new((0.0, 0.0, 0.0), motion_noise_cov());
+        assert_eq!(slam.map().landmarks.len(), 0);
+
+        slam.observation_update(&observe(&truth, (0.0, 0.0, 0.0)));
+        assert_eq!(slam.mean.len(), 9);
+        assert_eq!(slam.cov.dim(), (9, 9));
+        assert_eq!(slam.landmark_ids, vec![0, 1, 2]);
+        assert!(slam.map_error(&truth).unwrap() < 1e-4);
+    }
+
+    #[test]
+    fn test_observation_update_shrinks_landmark_cov() {
+        let truth = landmarks();
+        let pose = (0.0, 0.0, PI / 4.0);
+        let mut slam = EkfSlam::new(pose, motion_noise_cov());
+
+        slam.observation_update(&observe(&truth, pose));
+        let first = slam.landmark_cov(2).unwrap();
+        for _ in 0..5 {
+            slam.observation_update(&observe(&truth, pose));
+        }
+        let last = slam.landmark_cov(2).unwrap();
+        assert!(last[[0, 0]] < first[[0, 0]]);
+        assert!(last[[1, 1]] < first[[1, 1]]);
+        assert_eq!(slam.landmark_cov(5), None);
+    }
+
+    #[test]
+    fn test_singular_innovation_cov_is_skipped() {
+        let truth = landmarks();
+        let mut slam = EkfSlam::new((0.0, 0.0, 0.0), motion_noise_cov());
+        slam.observation_update(&observe(&truth, (0.0, 0.0, 0.0)));
+        let mean = slam.mean.clone();
+
+        slam = slam.set_observation_noise(0.0, 0.0);
+        slam.cov = Array2::zeros(slam.cov.dim());
+        slam.observation_update(&observe(&truth, (0.1, -0.1, 0.0)));
+        assert_eq!(slam.mean, mean);
+        assert!(slam.innovations.is_empty());
+    }
+
+    #[test]
+    fn test_motion_update_keeps_landmarks() {
+        let truth = landmarks();
+        let mut slam = EkfSlam::new((0.0, 0.0, 0.0), motion_noise_cov());
+        slam.observation_update(&observe(&truth, (0.0, 0.0, 0.0)));
+        let before = slam.map();
+
+        for _ in 0..10 {
+            slam.motion_update(0.2, 10.0 / 180.0 * PI, 0.1);
+        }
+        assert_eq!(
+            slam.map().landmarks[1].position,
+            before.landmarks[1].position
+        );
+        assert!(slam.cov[[0, 0]] > 1e-10);
+        assert!(slam.pose().0 > 0.19 && slam.pose().0 < 0.21);
+    }
+}
@@ -0,0 +1,280 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use polars::prelude::*;
+
+use crate::base::*;
+use crate::metrics::*;
+
+// Final state of one robot in one simulation of an experiment. A run which
+// panicked leaves a single failed result for robot 0 with a NaN pose.
+#[derive(Clone, Debug)]
+pub struct RunResult {
+    pub seed: u64,
+    pub robot: usize,
+    pub pose: (f32, f32, f32),
+    pub estimate: Option<(f32, f32, f32)>,
+    pub summary: Option<MetricsSummary>,
+    // the final estimate is farther than the failure threshold from the pose
+    // it was made at, or not finite
+    pub failed: bool,
+}
+
+// Runs the same simulation with many seeds, spread over the CPU cores.
+// `build` gives the world of one run; it has to seed the world with the given
+// seed and add the robots with `World::push` so that each run is
+// reproducible:
+//
+//     let experiment = Experiment::new(1000, |seed| {
+//         let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1).set_seed(seed);
+//         ...
+//         world
+//     });
+//     let df = experiment.run()?;
+pub struct Experiment<F> {
+    build: F,
+    runs: usize,
+    first_seed: u64,
+    threads: usize,
+    failure_threshold: f32,
+}
+
+impl<F: Fn(u64) -> World + Sync> Experiment<F> {
+    pub fn new(runs: usize, build: F) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        Experiment {
+            build,
+            runs,
+            first_seed: 0,
+            threads,
+            failure_threshold: 1.0,
+        }
+    }
+
+    // Runs use the seeds first_seed, first_seed + 1, ...
+    pub fn set_seed(mut self, first_seed: u64) -> Self {
+        self.first_seed = first_seed;
+        self
+    }
+
+    pub fn set_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // Distance between the final estimate and the true pose over which the
+    // estimator is considered lost
+    pub fn set_failure_threshold(mut self, failure_threshold: f32) -> Self {
+        self.failure_threshold = failure_threshold;
+        self
+    }
+
+    // Results of every robot of every run, sorted by seed and robot
+    pub fn results(&self) -> Vec<RunResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..self.threads.min(self.runs) {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= self.runs {
+                        break;
+                    }
+                    let seed = self.first_seed + i as u64;
+                    // a panicking run is a failure, not the end of the batch
+                    let run = panic::catch_unwind(AssertUnwindSafe(|| self.run_one(seed)))
+                        .unwrap_or_else(|_| vec![RunResult::panicked(seed)]);
+                    results.lock().unwrap().extend(run);
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|r| (r.seed, r.robot));
+        results
+    }
+
+    // One row per robot and run with the final pose, the final estimate, the
+    // errors of the estimator and whether it failed. The number of failures
+    // is `df.column("failed")?.bool()?.sum()`.
+    pub fn run(&self) -> PolarsResult<DataFrame> {
+        to_dataframe(&self.results())
+    }
+
+    fn run_one(&self, seed: u64) -> Vec<RunResult> {
+        let mut world = (self.build)(seed);
+        let mut estimates: Vec<Vec<Estimate>> = world
+            .objects()
+            .iter()
+            .map(|o| o.estimate().into_iter().collect())
+            .collect();
+        for _ in 0..world.max_iteration() {
+            world.step();
+            for (o, e) in world.objects().iter().zip(estimates.iter_mut()) {
+                e.extend(o.estimate());
+            }
+        }
+
+        world
+            .objects()
+            .iter()
+            .zip(estimates.iter())
+            .enumerate()
+            .map(|(robot, (o, es))| {
+                let pose = o.pose();
+                let poses = o.poses();
+                // the last estimate is about the pose before the last step
+                let observed = poses[poses.len().saturating_sub(2)];
+                let estimate = es.last().map(|e| e.pose);
+                let failed = estimate.is_some_and(|e| {
+                    let (dx, dy, _) = pose_error(observed, e);
+                    let error = (dx * dx + dy * dy).sqrt();
+                    error > self.failure_threshold || !error.is_finite()
+                });
+                let summary = if es.is_empty() {
+                    None
+                } else {
+                    Some(Evaluation::new(&o.poses(), es, 1).summary)
+                };
+                RunResult {
+                    seed,
+                    robot,
+                    pose,
+                    estimate,
+                    summary,
+                    failed,
+                }
+            })
+            .collect()
+    }
+}
+
+impl RunResult {
+    fn panicked(seed: u64) -> Self {
+        RunResult {
+            seed,
+            robot: 0,
+            pose: (f32::NAN, f32::NAN, f32::NAN),
+            estimate: None,
+            summary: None,
+            failed: true,
+        }
+    }
+}
+
+pub fn to_dataframe(results: &[RunResult]) -> PolarsResult<DataFrame> {
+    let estimate = |f: fn(&(f32, f32, f32)) -> f32| -> Vec<Option<f32>> {
+        results.iter().map(|r| r.estimate.as_ref().map(f)).collect()
+    };
+    let summary = |f: fn(&MetricsSummary) -> Option<f64>| -> Vec<Option<f64>> {
+        results
+            .iter()
+            .map(|r| r.summary.as_ref().and_then(f))
+            .collect()
+    };
+
+    df!(
+        "seed" => results.iter().map(|r| r.seed).collect::<Vec<u64>>(),
+        "robot" => results.iter().map(|r| r.robot as u32).collect::<Vec<u32>>(),
+        "x" => results.iter().map(|r| r.pose.0).collect::<Vec<f32>>(),
+        "y" => results.iter().map(|r| r.pose.1).collect::<Vec<f32>>(),
+        "theta" => results.iter().map(|r| r.pose.2).collect::<Vec<f32>>(),
+        "estimate_x" => estimate(|e| e.0),
+        "estimate_y" => estimate(|e| e.1),
+        "estimate_theta" => estimate(|e| e.2),
+        "ate_rmse" => summary(|s| Some(s.ate_rmse as f64)),
+        "heading_rmse" => summary(|s| Some(s.heading_rmse as f64)),
+        "rpe_translation_rmse" => summary(|s| s.rpe_translation_rmse.map(f64::from)),
+        "mean_nees" => summary(|s| s.mean_nees),
+        "mean_nis" => summary(|s| s.mean_nis),
+        "failed" => results.iter().map(|r| r.failed).collect::<Vec<bool>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kf::*;
+    use crate::robot::*;
+    use crate::test_util::*;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    fn build(seed: u64) -> World {
+        let map = landmarks();
+        let cov = motion_noise_cov();
+        let mut world = World::new(map.clone(), 5, 5, 2.0, 0.1).set_seed(seed);
+        let kf = KalmanFilter::new(map.clone(), (0.0, 0.0, 0.0), cov);
+        let agent = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, kf);
+        let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0));
+        let robot = Robot::new((0.0, 0.0, 0.0), &BLACK, agent, camera);
+        world.push(Box::new(robot));
+
+        let straight = Agent {
+            nu: 0.2,
+            omega: 0.0,
+        };
+        let camera = IdealCamera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0));
+        let robot = Robot::new((0.0, 0.0, 0.0), &RED, straight, camera);
+        world.push(Box::new(robot));
+        world
+    }
+
+    #[test]
+    fn test_results_do_not_depend_on_threads() {
+        let serial = Experiment::new(6, build)
+            .set_seed(10)
+            .set_threads(1)
+            .run()
+            .unwrap();
+        let parallel = Experiment::new(6, build)
+            .set_seed(10)
+            .set_threads(4)
+            .run()
+            .unwrap();
+        assert_eq!(serial.height(), 12);
+        assert!(serial.equals_missing(&parallel));
+
+        let seeds = serial.column("seed").unwrap().u64().unwrap();
+        assert_eq!(seeds.get(0), Some(10));
+        assert_eq!(seeds.get(11), Some(15));
+        let estimate_x = serial.column("estimate_x").unwrap().f32().unwrap();
+        assert!(estimate_x.get(0).is_some());
+        assert_eq!(estimate_x.get(1), None);
+    }
+
+    #[test]
+    fn test_failures_are_counted() {
+        let count = |threshold| {
+            let df = Experiment::new(4, build)
+                .set_failure_threshold(threshold)
+                .run()
+                .unwrap();
+            df.column("failed").unwrap().bool().unwrap().sum().unwrap()
+        };
+        assert_eq!(count(10.0), 0);
+        assert_eq!(count(0.0), 4);
+    }
+
+    #[test]
+    fn test_panicking_run_is_a_failure() {
+        let results = Experiment::new(4, |seed| {
+            if seed == 2 {
+                panic!("broken world");
+            }
+            build(seed)
+        })
+        .set_threads(2)
+        .set_failure_threshold(10.0)
+        .results();
+        assert_eq!(results.len(), 7);
+        let failed = results.iter().filter(|r| r.failed).collect::<Vec<_>>();
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].seed, failed[0].robot), (2, 0));
+        assert!(failed[0].pose.0.is_nan());
+    }
+}
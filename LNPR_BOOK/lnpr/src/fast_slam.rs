@@ -0,0 +1,452 @@
+use std::f64::consts::PI;
+
+use ndarray::{arr1, arr2, s, Array1, Array2};
+use ndarray_linalg::Inverse;
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::ekf_slam::map_error;
+use crate::kf::*;
+use crate::mcl::*;
+use crate::mvtnorm::*;
+use crate::plotters_ext::*;
+use crate::rng::*;
+
+// Landmark tracked by a small EKF inside a particle
+#[derive(Clone, Debug)]
+pub struct EstimatedLandmark {
+    pub id: i32,
+    pub mean: Array1<f64>,
+    pub cov: Array2<f64>,
+}
+
+impl EstimatedLandmark {
+    pub fn position(&self) -> (f32, f32) {
+        (self.mean[0] as f32, self.mean[1] as f32)
+    }
+}
+
+// MCL particle which also carries its own map
+#[derive(Clone, Debug)]
+pub struct MapParticle {
+    pub particle: Particle,
+    pub landmarks: Vec<EstimatedLandmark>,
+}
+
+impl MapParticle {
+    pub fn new(init_pose: (f32, f32, f32), weight: f32) -> Self {
+        MapParticle {
+            particle: Particle::new(init_pose, weight),
+            landmarks: Vec::new(),
+        }
+    }
+
+    pub fn landmark(&self, id: i32) -> Option<&EstimatedLandmark> {
+        self.landmarks.iter().find(|l| l.id == id)
+    }
+
+    fn add_landmark(&mut self, obs: &Observation, distance_dev_rate: f32, direction_dev: f32) {
+        let pose = self.particle.pose;
+        let (ell, phi) = obs.pos;
+        let (s, c) = (pose.2 + phi).sin_cos();
+        let mean = arr1(&[(pose.0 + ell * c) as f64, (pose.1 + ell * s) as f64]);
+        let (ell, s, c) = (ell as f64, s as f64, c as f64);
+        let gz = arr2(&[[c, -ell * s], [s, ell * c]]);
+        let q = mat_q(obs.pos.0 * distance_dev_rate, direction_dev);
+        self.landmarks.push(EstimatedLandmark {
+            id: obs.id,
+            mean,
+            cov: gz.dot(&q).dot(&gz.t()),
+        });
+    }
+
+    // EKF update of a landmark which has already been seen. Returns the
+    // likelihood of the observation.
+    fn update_landmark(
+        &mut self,
+        index: usize,
+        obs: &Observation,
+        distance_dev_rate: f32,
+        direction_dev: f32,
+    ) -> f64 {
+        let pose = self.particle.pose;
+        let landmark = &mut self.landmarks[index];
+        let estimated = IdealCamera::obs_fn(pose, landmark.position());
+        let h = -&mat_h(pose, landmark.position()).slice(s![.., 0..2]);
+        let q = mat_q(estimated.0 * distance_dev_rate, direction_dev);
+        let qz = h.dot(&landmark.cov).dot(&h.t()) + q;
+        let k = landmark.cov.dot(&h.t()).dot(&qz.inv().unwrap());
+
+        let innovation = arr1(&[
+            (obs.pos.0 - estimated.0) as f64,
+            normalize_angle(obs.pos.1 - estimated.1) as f64,
+        ]);
+        landmark.mean = &landmark.mean + &k.dot(&innovation);
+        landmark.cov = (Array2::<f64>::eye(2) - k.dot(&h)).dot(&landmark.cov);
+        gaussian_2d(&innovation, &qz)
+    }
+
+    // FastSLAM 2.0: draws the pose from the motion prediction refined by the
+    // observations of known landmarks, and weights the particle with the
+    // likelihood of those observations under the prediction.
+    fn proposal_update(
+        &mut self,
+        motion: (f32, f32, f32),
+        observation: &[Observation],
+        motion_noise_cov: &Array2<f64>,
+        distance_dev_rate: f32,
+        direction_dev: f32,
+        rng: &mut SimRng,
+    ) {
+        let (nu, omega, time) = motion;
+        let omega = if omega.abs() < 1e-5 { 1e-5 } else { omega };
+        let pose = self.particle.pose;
+        let predicted = IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, pose);
+        let a = mat_a(nu, omega, time, pose.2);
+        let r = a.dot(&mat_m(nu, omega, time, motion_noise_cov)).dot(&a.t())
+            + Array2::<f64>::eye(3) * 1e-10;
+
+        let mut mean = arr1(&[predicted.0 as f64, predicted.1 as f64, predicted.2 as f64]);
+        let mut cov = r.clone();
+        let mut likelihood = 1.0;
+        let mut known = false;
+        for obs in observation.iter() {
+            let landmark = match self.landmark(obs.id) {
+                Some(l) => l,
+                None => continue,
+            };
+            known = true;
+
+            let hx = mat_h(predicted, landmark.position());
+            let hm = -&hx.slice(s![.., 0..2]);
+            let q = mat_q(
+                IdealCamera::obs_fn(predicted, landmark.position()).0 * distance_dev_rate,
+                direction_dev,
+            );
+            let qz = hm.dot(&landmark.cov).dot(&hm.t()) + q;
+
+            // Weight with the prediction, before the pose is drawn
+            let estimated = IdealCamera::obs_fn(predicted, landmark.position());
+            let innovation = arr1(&[
+                (obs.pos.0 - estimated.0) as f64,
+                normalize_angle(obs.pos.1 - estimated.1) as f64,
+            ]);
+            likelihood *= gaussian_2d(&innovation, &(hx.dot(&r).dot(&hx.t()) + &qz));
+
+            // Refine the proposal with this observation
+            let current = (mean[0] as f32, mean[1] as f32, mean[2] as f32);
+            let estimated = IdealCamera::obs_fn(current, landmark.position());
+            let innovation = arr1(&[
+                (obs.pos.0 - estimated.0) as f64,
+                normalize_angle(obs.pos.1 - estimated.1) as f64,
+            ]);
+            let qz_inv = qz.inv().unwrap();
+            let refined = (hx.t().dot(&qz_inv).dot(&hx) + cov.inv().unwrap())
+                .inv()
+                .unwrap();
+            mean = &mean + &refined.dot(&hx.t()).dot(&qz_inv).dot(&innovation);
+            cov = refined;
+        }
+        self.particle.weight *= likelihood as f32;
+
+        if known {
+            let drawn = mvtnorm(rng, &mean, &cov);
+            self.particle.pose = (
+                drawn[0] as f32,
+                drawn[1] as f32,
+                normalize_angle(drawn[2] as f32),
+            );
+        } else {
+            self.particle
+                .motion_update(nu, omega, time, motion_noise_cov, rng);
+        }
+    }
+}
+
+// Density of a 2D gaussian with zero mean
+fn gaussian_2d(x: &Array1<f64>, cov: &Array2<f64>) -> f64 {
+    let det = cov[[0, 0]] * cov[[1, 1]] - cov[[0, 1]] * cov[[1, 0]];
+    let mahalanobis = x.dot(&cov.inv().unwrap().dot(x));
+    (-0.5 * mahalanobis).exp() / (2.0 * PI * det.sqrt())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FastSlamVersion {
+    One,
+    Two,
+}
+
+// FastSLAM
+#[derive(Clone)]
+pub struct FastSlam {
+    pub particles: Vec<MapParticle>,
+    pub motion_noise_cov: Array2<f64>,
+    pub distance_dev_rate: f32,
+    pub direction_dev: f32,
+    pub version: FastSlamVersion,
+    pub ml: MapParticle,
+    // FastSLAM 2.0 moves the particles when the observation arrives
+    pending_motion: Option<(f32, f32, f32)>,
+    rng: SimRng,
+}
+
+impl FastSlam {
+    pub fn new(init_pose: (f32, f32, f32), num: usize, motion_noise_cov: Array2<f64>) -> Self {
+        let mut particles = Vec::with_capacity(num);
+        for _ in 0..num {
+            particles.push(MapParticle::new(init_pose, 1.0 / num as f32));
+        }
+
+        FastSlam {
+            particles,
+            motion_noise_cov,
+            distance_dev_rate: 0.14,
+            direction_dev: 0.05,
+            version: FastSlamVersion::One,
+            ml: MapParticle::new(init_pose, 1.0),
+            pending_motion: None,
+            rng: SimRng::default(),
+        }
+    }
+
+    pub fn set_observation_noise(mut self, distance_dev_rate: f32, direction_dev: f32) -> Self {
+        self.distance_dev_rate = distance_dev_rate;
+        self.direction_dev = direction_dev;
+        self
+    }
+
+    pub fn set_version(mut self, version: FastSlamVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn set_rng(mut self, rng: SimRng) -> Self {
+        Estimator::set_rng(&mut self, rng);
+        self
+    }
+
+    // Map of the particle with the largest weight
+    pub fn map(&self) -> Map {
+        let mut map = Map::new();
+        for l in self.ml.landmarks.iter() {
+            map.landmarks.push(Landmark {
+                position: l.position(),
+                id: l.id,
+            });
+        }
+        map
+    }
+
+    pub fn map_error(&self, truth: &Map) -> Option<f32> {
+        map_error(&self.map(), truth)
+    }
+
+    pub fn landmark_cov(&self, id: i32) -> Option<Array2<f64>> {
+        self.ml.landmark(id).map(|l| l.cov.clone())
+    }
+
+    fn move_particles(&mut self, nu: f32, omega: f32, time: f32) {
+        let (cov, rng) = (&self.motion_noise_cov, &mut self.rng);
+        self.particles.iter_mut().for_each(|p| {
+            p.particle.motion_update(nu, omega, time, cov, rng);
+        });
+    }
+
+    fn normalize(&mut self) {
+        let num = self.particles.len() as f32;
+        let total: f32 = self.particles.iter().map(|p| p.particle.weight).sum();
+        if total <= 0.0 || !total.is_finite() {
+            self.particles
+                .iter_mut()
+                .for_each(|p| p.particle.weight = 1.0 / num);
+            return;
+        }
+        self.particles
+            .iter_mut()
+            .for_each(|p| p.particle.weight /= total);
+    }
+
+    pub fn resampling(&mut self) {
+        let weights = self
+            .particles
+            .iter()
+            .map(|p| p.particle.weight)
+            .collect::<Vec<f32>>();
+        let step = 1.0 / weights.len() as f32;
+        self.particles = systematic_sampling(&weights, &mut self.rng)
+            .into_iter()
+            .map(|i| {
+                let mut p = self.particles[i].clone();
+                p.particle.weight = step;
+                p
+            })
+            .collect();
+    }
+}
+
+impl Estimator for FastSlam {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        if let Some((nu, omega, time)) = self.pending_motion.take() {
+            self.move_particles(nu, omega, time);
+        }
+        match self.version {
+            FastSlamVersion::One => self.move_particles(nu, omega, time),
+            FastSlamVersion::Two => self.pending_motion = Some((nu, omega, time)),
+        }
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        let (distance_dev_rate, direction_dev) = (self.distance_dev_rate, self.direction_dev);
+        if let Some(motion) = self.pending_motion.take() {
+            let (cov, rng) = (&self.motion_noise_cov, &mut self.rng);
+            self.particles.iter_mut().for_each(|p| {
+                p.proposal_update(
+                    motion,
+                    observation,
+                    cov,
+                    distance_dev_rate,
+                    direction_dev,
+                    rng,
+                );
+            });
+        }
+
+        let version = self.version;
+        self.particles.iter_mut().for_each(|p| {
+            for obs in observation.iter() {
+                match p.landmarks.iter().position(|l| l.id == obs.id) {
+                    Some(i) => {
+                        let likelihood =
+                            p.update_landmark(i, obs, distance_dev_rate, direction_dev);
+                        // FastSLAM 2.0 already weighted the particle in the proposal
+                        if version == FastSlamVersion::One {
+                            p.particle.weight *= likelihood as f32;
+                        }
+                    }
+                    None => p.add_landmark(obs, distance_dev_rate, direction_dev),
+                }
+            }
+        });
+
+        self.normalize();
+        self.ml = self
+            .particles
+            .iter()
+            .fold(None, |ml: Option<&MapParticle>, p| match ml {
+                Some(m) if m.particle.weight >= p.particle.weight => Some(m),
+                _ => Some(p),
+            })
+            .unwrap_or(&self.ml)
+            .clone();
+        self.resampling();
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        self.ml.particle.pose
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        Some(self.map())
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.rng = rng;
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        self.particles.iter().for_each(|p| {
+            let (x, y, t) = p.particle.pose;
+            let from = translate_coord(drawing_area, x, y, xlim, ylim);
+            let to = (
+                from.0 + (20.0 * t.cos()) as i32,
+                from.1 + (20.0 * -t.sin()) as i32,
+            );
+
+            coord_spec
+                .draw(&Quiver::new(from, to, Into::<ShapeStyle>::into(&BLUE)))
+                .unwrap();
+        });
+
+        self.map().landmarks.iter().for_each(|l| {
+            let pos = translate_coord(drawing_area, l.position.0, l.position.1, xlim, ylim);
+            coord_spec
+                .draw(&Cross::new(pos, 6, Into::<ShapeStyle>::into(&BLUE)))
+                .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::f32::consts::PI;
+
+    fn observe(map: &Map, pose: (f32, f32, f32)) -> Vec<Observation> {
+        map.landmarks
+            .iter()
+            .map(|l| Observation::new(IdealCamera::obs_fn(pose, l.position), l.id))
+            .collect()
+    }
+
+    #[test]
+    fn test_particles_build_their_own_map() {
+        let truth = landmarks();
+        let mut slam = FastSlam::new((0.0, 0.0, 0.0), 10, motion_noise_cov());
+        slam.observation_update(&observe(&truth, (0.0, 0.0, 0.0)));
+
+        slam.particles
+            .iter()
+            .for_each(|p| assert_eq!(p.landmarks.len(), 3));
+        let map = slam.map();
+        assert_eq!(map.landmarks.len(), 3);
+        assert!((map.landmarks[2].position.0 - 3.0).abs() < 1e-4);
+        assert!((map.landmarks[2].position.1 - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_repeated_observation_shrinks_landmark_cov() {
+        let truth = landmarks();
+        let pose = (0.0, 0.0, PI / 4.0);
+        let mut slam = FastSlam::new(pose, 5, motion_noise_cov());
+        slam.observation_update(&observe(&truth, pose));
+        let first = slam.landmark_cov(2).unwrap();
+        for _ in 0..5 {
+            slam.observation_update(&observe(&truth, pose));
+        }
+        let last = slam.landmark_cov(2).unwrap();
+        assert!(last[[0, 0]] < first[[0, 0]]);
+        assert!(last[[1, 1]] < first[[1, 1]]);
+    }
+
+    #[test]
+    fn test_fast_slam_2_tracks_pose() {
+        let truth = landmarks();
+        let mut pose = (0.0, 0.0, 0.0);
+        let mut slam = FastSlam::new(pose, 20, motion_noise_cov())
+            .set_version(FastSlamVersion::Two)
+            .set_rng(SimRng::seed_from_u64(2));
+        slam.observation_update(&observe(&truth, pose));
+
+        for _ in 0..20 {
+            slam.motion_update(0.2, 10.0 / 180.0 * PI, 0.1);
+            pose = IdealRobot::<Agent, IdealCamera>::state_transition(
+                0.2,
+                10.0 / 180.0 * PI,
+                0.1,
+                pose,
+            );
+            slam.observation_update(&observe(&truth, pose));
+        }
+
+        let estimated = slam.pose();
+        assert!((estimated.0 - pose.0).abs() < 0.2);
+        assert!((estimated.1 - pose.1).abs() < 0.2);
+    }
+}
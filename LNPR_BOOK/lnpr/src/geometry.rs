@@ -0,0 +1,128 @@
+// Plane geometry on points (x, y) shared by maps, sensors and collisions
+
+fn cross(a: (f32, f32), b: (f32, f32)) -> f32 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn sub(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+// Where the segment p1-p2 crosses the segment q1-q2, as the ratio of the way
+// from p1 to p2. None if they do not cross or are parallel.
+pub fn segment_intersection(
+    p1: (f32, f32),
+    p2: (f32, f32),
+    q1: (f32, f32),
+    q2: (f32, f32),
+) -> Option<f32> {
+    let r = sub(p2, p1);
+    let s = sub(q2, q1);
+    let denom = cross(r, s);
+    if denom == 0.0 {
+        return None;
+    }
+    let qp = sub(q1, p1);
+    let t = cross(qp, s) / denom;
+    let u = cross(qp, r) / denom;
+    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Point of the segment a-b nearest to p
+pub fn closest_point_on_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let ab = sub(b, a);
+    let length2 = ab.0 * ab.0 + ab.1 * ab.1;
+    if length2 == 0.0 {
+        return a;
+    }
+    let ap = sub(p, a);
+    let t = ((ap.0 * ab.0 + ap.1 * ab.1) / length2).clamp(0.0, 1.0);
+    (a.0 + t * ab.0, a.1 + t * ab.1)
+}
+
+pub fn distance_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let c = closest_point_on_segment(p, a, b);
+    ((p.0 - c.0).powi(2) + (p.1 - c.1).powi(2)).sqrt()
+}
+
+// How far a ray from `origin` towards `direction` goes before it enters the
+// circle, within `max_range`. None if it misses or starts inside.
+pub fn ray_circle_intersection(
+    origin: (f32, f32),
+    direction: f32,
+    max_range: f32,
+    center: (f32, f32),
+    radius: f32,
+) -> Option<f32> {
+    let d = (direction.cos(), direction.sin());
+    let oc = sub(origin, center);
+    let b = oc.0 * d.0 + oc.1 * d.1;
+    let c = oc.0 * oc.0 + oc.1 * oc.1 - radius * radius;
+    let disc = b * b - c;
+    if c < 0.0 || disc < 0.0 {
+        return None;
+    }
+    let t = -b - disc.sqrt();
+    if (0.0..=max_range).contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Even-odd rule; points on the border may go either way
+pub fn point_in_polygon(p: (f32, f32), vertices: &[(f32, f32)]) -> bool {
+    let mut inside = false;
+    let n = vertices.len();
+    for i in 0..n {
+        let (a, b) = (vertices[i], vertices[(i + 1) % n]);
+        if (a.1 > p.1) != (b.1 > p.1) {
+            let x = a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0);
+            if p.0 < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        let t = segment_intersection((0.0, 0.0), (4.0, 0.0), (1.0, -1.0), (1.0, 1.0));
+        assert_eq!(t, Some(0.25));
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (4.0, 0.0), (5.0, -1.0), (5.0, 1.0)),
+            None
+        );
+        assert_eq!(
+            segment_intersection((0.0, 0.0), (4.0, 0.0), (0.0, 1.0), (4.0, 1.0)),
+            None
+        );
+
+        assert_eq!(distance_to_segment((1.0, 2.0), (0.0, 0.0), (4.0, 0.0)), 2.0);
+        assert_eq!(distance_to_segment((7.0, 4.0), (0.0, 0.0), (4.0, 0.0)), 5.0);
+
+        let t = ray_circle_intersection((0.0, 0.0), 0.0, 5.0, (3.0, 0.0), 1.0);
+        assert!((t.unwrap() - 2.0).abs() < 1e-5);
+        assert_eq!(
+            ray_circle_intersection((0.0, 0.0), 0.0, 1.5, (3.0, 0.0), 1.0),
+            None
+        );
+        assert_eq!(
+            ray_circle_intersection((0.0, 0.0), 0.0, 5.0, (3.0, 2.0), 1.0),
+            None
+        );
+
+        let square = [(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0)];
+        assert!(point_in_polygon((1.0, 1.0), &square));
+        assert!(!point_in_polygon((3.0, 1.0), &square));
+    }
+}
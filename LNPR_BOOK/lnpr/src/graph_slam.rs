@@ -0,0 +1,310 @@
+use ndarray::{arr1, s, Array1, Array2};
+use ndarray_linalg::{Inverse, Solve};
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::ekf_slam::map_error;
+use crate::kf::*;
+
+// Graph-based SLAM. Poses are dead reckoned from the commanded velocities
+// while the robot moves; `optimize` then solves the whole pose/landmark graph.
+#[derive(Clone)]
+pub struct GraphSlam {
+    pub poses: Vec<(f32, f32, f32)>,
+    // Velocities and time which moved poses[i] to poses[i + 1]
+    pub motions: Vec<(f32, f32, f32)>,
+    // Observations taken at poses[i]
+    pub observations: Vec<Vec<Observation>>,
+    pub map: Map,
+    pub motion_noise_cov: Array2<f64>,
+    pub distance_dev_rate: f32,
+    pub direction_dev: f32,
+}
+
+impl GraphSlam {
+    pub fn new(init_pose: (f32, f32, f32), motion_noise_cov: Array2<f64>) -> Self {
+        GraphSlam {
+            poses: vec![init_pose],
+            motions: Vec::new(),
+            observations: vec![Vec::new()],
+            map: Map::new(),
+            motion_noise_cov,
+            distance_dev_rate: 0.14,
+            direction_dev: 0.05,
+        }
+    }
+
+    pub fn set_observation_noise(mut self, distance_dev_rate: f32, direction_dev: f32) -> Self {
+        self.distance_dev_rate = distance_dev_rate;
+        self.direction_dev = direction_dev;
+        self
+    }
+
+    // Trajectory obtained only from the odometry edges
+    pub fn odometry(&self) -> Vec<(f32, f32, f32)> {
+        let mut poses = vec![self.poses[0]];
+        for (nu, omega, time) in self.motions.iter() {
+            let last = poses[poses.len() - 1];
+            poses.push(transition(*nu, *omega, *time, last));
+        }
+        poses
+    }
+
+    pub fn map_error(&self, truth: &Map) -> Option<f32> {
+        map_error(&self.map, truth)
+    }
+
+    fn landmark_index(&self, id: i32) -> Option<usize> {
+        self.map.landmarks.iter().position(|l| l.id == id)
+    }
+
+    fn state(&self) -> Array1<f64> {
+        let mut x = Vec::with_capacity(3 * self.poses.len() + 2 * self.map.landmarks.len());
+        for p in self.poses.iter() {
+            x.extend_from_slice(&[p.0 as f64, p.1 as f64, p.2 as f64]);
+        }
+        for l in self.map.landmarks.iter() {
+            x.extend_from_slice(&[l.position.0 as f64, l.position.1 as f64]);
+        }
+        Array1::from(x)
+    }
+
+    fn set_state(&mut self, x: &Array1<f64>) {
+        let offset = 3 * self.poses.len();
+        for (i, p) in self.poses.iter_mut().enumerate() {
+            *p = (
+                x[3 * i] as f32,
+                x[3 * i + 1] as f32,
+                normalize_angle(x[3 * i + 2] as f32),
+            );
+        }
+        for (i, l) in self.map.landmarks.iter_mut().enumerate() {
+            l.position = (x[offset + 2 * i] as f32, x[offset + 2 * i + 1] as f32);
+        }
+    }
+
+    // Builds the normal equations of the graph around `x`. Returns H, b and the
+    // total weighted squared error.
+    fn linearize(&self, x: &Array1<f64>) -> (Array2<f64>, Array1<f64>, f64) {
+        let n = x.len();
+        let offset = 3 * self.poses.len();
+        let mut h = Array2::<f64>::zeros((n, n));
+        let mut b = Array1::<f64>::zeros(n);
+        let mut error = 0.0;
+        let pose_at = |i: usize| (x[3 * i] as f32, x[3 * i + 1] as f32, x[3 * i + 2] as f32);
+
+        // The first pose anchors the graph
+        for i in 0..3 {
+            h[[i, i]] += 1e8;
+        }
+
+        for (t, (nu, omega, time)) in self.motions.iter().enumerate() {
+            let omega = if omega.abs() < 1e-5 { 1e-5 } else { *omega };
+            let prev = pose_at(t);
+            let cur = pose_at(t + 1);
+            let predicted = transition(*nu, omega, *time, prev);
+            let e = arr1(&[
+                (predicted.0 - cur.0) as f64,
+                (predicted.1 - cur.1) as f64,
+                normalize_angle(predicted.2 - cur.2) as f64,
+            ]);
+
+            let a = mat_a(*nu, omega, *time, prev.2);
+            let r = a
+                .dot(&mat_m(*nu, omega, *time, &self.motion_noise_cov))
+                .dot(&a.t())
+                + Array2::<f64>::eye(3) * 1e-6;
+            let info = r.inv().unwrap();
+            let jacobians = [
+                (3 * t, mat_f(*nu, omega, *time, prev.2)),
+                (3 * (t + 1), -Array2::<f64>::eye(3)),
+            ];
+            add_edge(&mut h, &mut b, &e, &info, &jacobians);
+            error += e.dot(&info.dot(&e));
+        }
+
+        for (t, observation) in self.observations.iter().enumerate() {
+            let pose = pose_at(t);
+            for obs in observation.iter() {
+                let j = match self.landmark_index(obs.id) {
+                    Some(j) => offset + 2 * j,
+                    None => continue,
+                };
+                let landmark = (x[j] as f32, x[j + 1] as f32);
+                let estimated = IdealCamera::obs_fn(pose, landmark);
+                let e = arr1(&[
+                    (estimated.0 - obs.pos.0) as f64,
+                    normalize_angle(estimated.1 - obs.pos.1) as f64,
+                ]);
+
+                let info = mat_q(obs.pos.0 * self.distance_dev_rate, self.direction_dev)
+                    .inv()
+                    .unwrap();
+                let hx = mat_h(pose, landmark);
+                let hm = -&hx.slice(s![.., 0..2]);
+                add_edge(&mut h, &mut b, &e, &info, &[(3 * t, hx), (j, hm)]);
+                error += e.dot(&info.dot(&e));
+            }
+        }
+        (h, b, error)
+    }
+
+    // Weighted squared error of the whole graph at the current estimate
+    pub fn error(&self) -> f64 {
+        self.linearize(&self.state()).2
+    }
+
+    // Gauss-Newton over every pose and landmark. Returns the final error.
+    pub fn optimize(&mut self, max_iteration: usize) -> f64 {
+        let mut x = self.state();
+        for _ in 0..max_iteration {
+            let (h, b, _) = self.linearize(&x);
+            let delta = h.solve(&(-b)).unwrap();
+            x = &x + &delta;
+            if delta.iter().map(|d| d.abs()).fold(0.0, f64::max) < 1e-6 {
+                break;
+            }
+        }
+        self.set_state(&x);
+        self.error()
+    }
+}
+
+fn transition(nu: f32, omega: f32, time: f32, pose: (f32, f32, f32)) -> (f32, f32, f32) {
+    IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, pose)
+}
+
+// Adds J^T Ω J and J^T Ω e of one edge. `jacobians` holds the position of each
+// node in the state vector together with the jacobian of the error.
+fn add_edge(
+    h: &mut Array2<f64>,
+    b: &mut Array1<f64>,
+    e: &Array1<f64>,
+    info: &Array2<f64>,
+    jacobians: &[(usize, Array2<f64>)],
+) {
+    for (i, ji) in jacobians.iter() {
+        let wi = ji.t().dot(info);
+        let (_, di) = ji.dim();
+        b.slice_mut(s![*i..*i + di])
+            .zip_mut_with(&wi.dot(e), |b, v| *b += v);
+        for (j, jj) in jacobians.iter() {
+            let (_, dj) = jj.dim();
+            h.slice_mut(s![*i..*i + di, *j..*j + dj])
+                .zip_mut_with(&wi.dot(jj), |h, v| *h += v);
+        }
+    }
+}
+
+impl Estimator for GraphSlam {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        let last = self.poses[self.poses.len() - 1];
+        self.motions.push((nu, omega, time));
+        self.poses.push(transition(nu, omega, time, last));
+        self.observations.push(Vec::new());
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        let pose = self.pose();
+        for obs in observation.iter() {
+            if self.landmark_index(obs.id).is_none() {
+                let (ell, phi) = obs.pos;
+                self.map.landmarks.push(Landmark {
+                    position: (
+                        pose.0 + ell * (pose.2 + phi).cos(),
+                        pose.1 + ell * (pose.2 + phi).sin(),
+                    ),
+                    id: obs.id,
+                });
+            }
+        }
+        let last = self.observations.len() - 1;
+        self.observations[last].extend_from_slice(observation);
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        self.poses[self.poses.len() - 1]
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        Some(self.map.clone())
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        let points = self
+            .poses
+            .iter()
+            .map(|p| translate_coord(drawing_area, p.0, p.1, xlim, ylim))
+            .collect::<Vec<(i32, i32)>>();
+        coord_spec
+            .draw(&PathElement::new(points, Into::<ShapeStyle>::into(&BLUE)))
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::f32::consts::PI;
+
+    fn observe(map: &Map, pose: (f32, f32, f32)) -> Vec<Observation> {
+        map.landmarks
+            .iter()
+            .map(|l| Observation::new(IdealCamera::obs_fn(pose, l.position), l.id))
+            .collect()
+    }
+
+    // Runs the robot along a circle; the estimator gets biased velocities
+    fn run(slam: &mut GraphSlam, truth: &Map) -> Vec<(f32, f32, f32)> {
+        let (nu, omega, time) = (0.2, 10.0 / 180.0 * PI, 0.5);
+        let mut pose = slam.poses[0];
+        let mut poses = vec![pose];
+        slam.observation_update(&observe(truth, pose));
+        for _ in 0..20 {
+            slam.motion_update(nu * 1.1, omega * 0.9, time);
+            pose = transition(nu, omega, time, pose);
+            poses.push(pose);
+            slam.observation_update(&observe(truth, pose));
+        }
+        poses
+    }
+
+    #[test]
+    fn test_record_graph() {
+        let truth = landmarks();
+        let mut slam = GraphSlam::new((0.0, 0.0, 0.0), motion_noise_cov());
+        run(&mut slam, &truth);
+
+        assert_eq!(slam.poses.len(), 21);
+        assert_eq!(slam.motions.len(), 20);
+        assert_eq!(slam.observations.len(), 21);
+        assert_eq!(slam.map.landmarks.len(), 3);
+        assert_eq!(slam.odometry(), slam.poses);
+    }
+
+    #[test]
+    fn test_optimize_removes_drift() {
+        let truth = landmarks();
+        let mut slam = GraphSlam::new((0.0, 0.0, 0.0), motion_noise_cov());
+        let poses = run(&mut slam, &truth);
+
+        let drift = |estimated: &[(f32, f32, f32)]| {
+            let (e, t) = (estimated[estimated.len() - 1], poses[poses.len() - 1]);
+            ((e.0 - t.0).powi(2) + (e.1 - t.1).powi(2)).sqrt()
+        };
+        let before = drift(&slam.odometry());
+        slam.optimize(20);
+        let after = drift(&slam.poses);
+
+        assert!(after < before);
+        assert!(after < 0.05);
+        assert!(slam.map_error(&truth).unwrap() < 0.05);
+    }
+}
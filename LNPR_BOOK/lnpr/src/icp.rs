@@ -0,0 +1,482 @@
+use ndarray::{arr2, Array1, Array2};
+use ndarray_linalg::Inverse;
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::kf::*;
+use crate::plotters_ext::*;
+
+// What the distance between two scans is measured by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IcpMethod {
+    // from each point to its nearest point of the reference
+    PointToPoint,
+    // from each point to the line through its nearest point of the reference
+    // and the neighbors of that point, which lets scans slide along walls
+    PointToLine,
+}
+
+// Alignment of a scan on a reference scan
+#[derive(Clone, Debug)]
+pub struct IcpResult {
+    // pose of the scan in the frame of the reference
+    pub pose: (f32, f32, f32),
+    // covariance of (x, y, theta) of `pose`
+    pub cov: Array2<f64>,
+    // root mean square of the residuals at `pose`
+    pub error: f32,
+    pub correspondences: usize,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+// Iterative closest point matching between two range scans
+#[derive(Clone, Debug)]
+pub struct Icp {
+    pub method: IcpMethod,
+    pub max_iterations: usize,
+    // points farther than this from the reference are left out
+    pub max_distance: f32,
+    // the matching stops once a step moves less than this
+    pub tolerance: f32,
+}
+
+// Points hit by the beams of `scan` in the frame of the sensor, in the order
+// of the beams. Readings at or beyond `max_range` hit nothing.
+pub fn scan_points(scan: &[Observation], max_range: f32) -> Vec<(f32, f32)> {
+    scan.iter()
+        .filter(|obs| obs.pos.0 < max_range)
+        .map(|obs| {
+            let (distance, direction) = obs.pos;
+            (distance * direction.cos(), distance * direction.sin())
+        })
+        .collect()
+}
+
+// `pose` moved by `relative`, given in the frame of `pose`
+pub fn compose(pose: (f32, f32, f32), relative: (f32, f32, f32)) -> (f32, f32, f32) {
+    let (s, c) = pose.2.sin_cos();
+    (
+        pose.0 + c * relative.0 - s * relative.1,
+        pose.1 + s * relative.0 + c * relative.1,
+        normalize_angle(pose.2 + relative.2),
+    )
+}
+
+fn transform(pose: (f32, f32, f32), p: (f32, f32)) -> (f32, f32) {
+    let (s, c) = pose.2.sin_cos();
+    (pose.0 + c * p.0 - s * p.1, pose.1 + s * p.0 + c * p.1)
+}
+
+// One residual of a correspondence and its derivative by (x, y, theta) of a
+// move of the scan in the frame of the reference
+struct Residual {
+    value: f64,
+    jacobian: [f64; 3],
+}
+
+impl Icp {
+    pub fn new(method: IcpMethod) -> Self {
+        Icp {
+            method,
+            max_iterations: 50,
+            max_distance: 0.5,
+            tolerance: 1e-5,
+        }
+    }
+
+    pub fn set_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn set_max_distance(mut self, max_distance: f32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn set_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    // Unit normals of the reference, from the line through the neighbors of
+    // each point. Neighbors beyond `max_distance` are on another surface and
+    // left out; a point without any gets a zero normal and counts for nothing.
+    fn normals(&self, reference: &[(f32, f32)]) -> Vec<(f32, f32)> {
+        let n = reference.len();
+        let near = |a: (f32, f32), b: (f32, f32)| {
+            ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt() <= self.max_distance
+        };
+        (0..n)
+            .map(|k| {
+                let p = reference[k];
+                let (before, after) = (
+                    reference[k.saturating_sub(1)],
+                    reference[(k + 1).min(n - 1)],
+                );
+                let a = if near(before, p) { before } else { p };
+                let b = if near(after, p) { after } else { p };
+                let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+                let norm = (dx * dx + dy * dy).sqrt();
+                if norm < 1e-9 {
+                    (0.0, 0.0)
+                } else {
+                    (-dy / norm, dx / norm)
+                }
+            })
+            .collect()
+    }
+
+    // Residuals of the points of `current` put at `pose`, paired with their
+    // nearest points of the reference
+    fn residuals(
+        &self,
+        reference: &[(f32, f32)],
+        normals: &[(f32, f32)],
+        current: &[(f32, f32)],
+        pose: (f32, f32, f32),
+    ) -> Vec<Residual> {
+        let mut residuals = Vec::new();
+        for &p in current.iter() {
+            let p = transform(pose, p);
+            let nearest = reference
+                .iter()
+                .enumerate()
+                .map(|(k, q)| (k, (p.0 - q.0).powi(2) + (p.1 - q.1).powi(2)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            let k = match nearest {
+                Some((k, d2)) if d2.sqrt() <= self.max_distance => k,
+                _ => continue,
+            };
+            let q = reference[k];
+            let (px, py) = (p.0 as f64, p.1 as f64);
+            let (ex, ey) = ((p.0 - q.0) as f64, (p.1 - q.1) as f64);
+            match self.method {
+                IcpMethod::PointToPoint => {
+                    residuals.push(Residual {
+                        value: ex,
+                        jacobian: [1.0, 0.0, -py],
+                    });
+                    residuals.push(Residual {
+                        value: ey,
+                        jacobian: [0.0, 1.0, px],
+                    });
+                }
+                IcpMethod::PointToLine => {
+                    let (nx, ny) = (normals[k].0 as f64, normals[k].1 as f64);
+                    residuals.push(Residual {
+                        value: nx * ex + ny * ey,
+                        jacobian: [nx, ny, -nx * py + ny * px],
+                    });
+                }
+            }
+        }
+        residuals
+    }
+
+    // J^T J, slightly damped so that a direction the scans cannot tell, like
+    // along a corridor, gets a large variance rather than none, and J^T r
+    fn normal_equations(residuals: &[Residual]) -> (Array2<f64>, Array1<f64>) {
+        let mut h = Array2::<f64>::eye(3) * 1e-9;
+        let mut g = Array1::<f64>::zeros(3);
+        for r in residuals.iter() {
+            for a in 0..3 {
+                g[a] += r.jacobian[a] * r.value;
+                for b in 0..3 {
+                    h[[a, b]] += r.jacobian[a] * r.jacobian[b];
+                }
+            }
+        }
+        (h, g)
+    }
+
+    // Pose of the scan `current` in the frame of the scan `reference`,
+    // starting from `initial`. None if either has fewer than three points or
+    // too few of them correspond.
+    pub fn align(
+        &self,
+        reference: &[(f32, f32)],
+        current: &[(f32, f32)],
+        initial: (f32, f32, f32),
+    ) -> Option<IcpResult> {
+        if reference.len() < 3 || current.len() < 3 {
+            return None;
+        }
+        let normals = self.normals(reference);
+        let mut pose = initial;
+        let mut converged = false;
+        let mut iterations = 0;
+        while iterations < self.max_iterations {
+            iterations += 1;
+            let residuals = self.residuals(reference, &normals, current, pose);
+            if residuals.len() < 3 {
+                return None;
+            }
+            // Gauss-Newton step, which is exact for the translation and
+            // linearized for the rotation
+            let (h, g) = Self::normal_equations(&residuals);
+            let step = -h.inv().ok()?.dot(&g);
+            let (dx, dy, dt) = (step[0] as f32, step[1] as f32, step[2] as f32);
+            // the step moves the scan in the frame of the reference
+            let moved = transform((dx, dy, dt), (pose.0, pose.1));
+            pose = (moved.0, moved.1, normalize_angle(pose.2 + dt));
+            if (dx * dx + dy * dy + dt * dt).sqrt() < self.tolerance {
+                converged = true;
+                break;
+            }
+        }
+
+        let residuals = self.residuals(reference, &normals, current, pose);
+        let dof = residuals.len().saturating_sub(3).max(1) as f64;
+        let squares: f64 = residuals.iter().map(|r| r.value * r.value).sum();
+        let (h, _) = Self::normal_equations(&residuals);
+        let cov = h.inv().ok()? * (squares / dof);
+        let correspondences = match self.method {
+            IcpMethod::PointToPoint => residuals.len() / 2,
+            IcpMethod::PointToLine => residuals.len(),
+        };
+        Some(IcpResult {
+            pose,
+            cov,
+            error: (squares / residuals.len().max(1) as f64).sqrt() as f32,
+            correspondences,
+            iterations,
+            converged,
+        })
+    }
+}
+
+// Odometry from matching each range scan on the one before. The commanded
+// motion since the last scan is the first guess of the match, and the pose
+// is only dead reckoned when the scans cannot be matched.
+#[derive(Clone, Debug)]
+pub struct ScanOdometry {
+    pub icp: Icp,
+    pub pose: (f32, f32, f32),
+    pub cov: Array2<f64>,
+    pub max_range: f32,
+    pub motion_noise_cov: Array2<f64>,
+    // match of the last scan on the one before, None if it was dead reckoned
+    pub last_match: Option<IcpResult>,
+    reference: Vec<(f32, f32)>,
+    guess: (f32, f32, f32),
+    // covariance of `guess`, used when the pose is dead reckoned
+    guess_cov: Array2<f64>,
+}
+
+impl ScanOdometry {
+    pub fn new(
+        init_pose: (f32, f32, f32),
+        max_range: f32,
+        motion_noise_cov: Array2<f64>,
+        icp: Icp,
+    ) -> Self {
+        ScanOdometry {
+            icp,
+            pose: init_pose,
+            cov: Array2::zeros((3, 3)),
+            max_range,
+            motion_noise_cov,
+            last_match: None,
+            reference: Vec::new(),
+            guess: (0.0, 0.0, 0.0),
+            guess_cov: Array2::zeros((3, 3)),
+        }
+    }
+
+    // Adds the covariance of `relative` to that of the pose
+    fn propagate(&mut self, relative: (f32, f32, f32), relative_cov: &Array2<f64>) {
+        let (s, c) = (self.pose.2.sin() as f64, self.pose.2.cos() as f64);
+        let (dx, dy) = (relative.0 as f64, relative.1 as f64);
+        let f = arr2(&[
+            [1.0, 0.0, -s * dx - c * dy],
+            [0.0, 1.0, c * dx - s * dy],
+            [0.0, 0.0, 1.0],
+        ]);
+        let r = arr2(&[[c, -s, 0.0], [s, c, 0.0], [0.0, 0.0, 1.0]]);
+        self.cov = f.dot(&self.cov).dot(&f.t()) + r.dot(relative_cov).dot(&r.t());
+    }
+}
+
+impl Estimator for ScanOdometry {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        // The jacobians are singular at omega = 0
+        let omega = if omega.abs() < 1e-5 { 1e-5 } else { omega };
+        let m = mat_m(nu, omega, time, &self.motion_noise_cov);
+        let a = mat_a(nu, omega, time, self.guess.2);
+        let f = mat_f(nu, omega, time, self.guess.2);
+        self.guess_cov = f.dot(&self.guess_cov).dot(&f.t()) + a.dot(&m).dot(&a.t());
+        self.guess =
+            IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, self.guess);
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        let current = scan_points(observation, self.max_range);
+        self.last_match = self
+            .icp
+            .align(&self.reference, &current, self.guess)
+            .filter(|m| m.converged);
+        match self.last_match.clone() {
+            Some(m) => {
+                self.propagate(m.pose, &m.cov);
+                self.pose = compose(self.pose, m.pose);
+            }
+            None => {
+                let guess_cov = self.guess_cov.clone();
+                self.propagate(self.guess, &guess_cov);
+                self.pose = compose(self.pose, self.guess);
+            }
+        }
+        self.reference = current;
+        self.guess = (0.0, 0.0, 0.0);
+        self.guess_cov = Array2::zeros((3, 3));
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        self.pose
+    }
+
+    fn pose_cov(&self) -> Option<Array2<f64>> {
+        Some(self.cov.clone())
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let (x, y, t) = self.pose;
+        let from = translate_coord(drawing_area, x, y, xlim, ylim);
+        let to = (
+            from.0 + (20.0 * t.cos()) as i32,
+            from.1 + (20.0 * -t.sin()) as i32,
+        );
+
+        let coord_spec = drawing_area.strip_coord_spec();
+        coord_spec
+            .draw(&Quiver::new(from, to, Into::<ShapeStyle>::into(&GREEN)))
+            .unwrap();
+
+        let ellipse = ErrorEllipse::new((x, y), &self.cov, 0.99, GREEN)
+            .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+        coord_spec.draw(&ellipse).unwrap();
+
+        // the last scan where the odometry puts it
+        for &p in self.reference.iter() {
+            let (px, py) = transform(self.pose, p);
+            let at = translate_coord(drawing_area, px, py, xlim, ylim);
+            coord_spec
+                .draw(&Circle::new(at, 1, GREEN.filled()))
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::*;
+    use crate::robot::*;
+    use crate::test_util::*;
+    use std::f32::consts::PI;
+
+    fn room() -> Map {
+        let mut map = Map::new();
+        map.append_wall((-3.0, -2.0), (3.0, -2.0));
+        map.append_wall((3.0, -2.0), (3.0, 2.0));
+        map.append_wall((3.0, 2.0), (-3.0, 2.0));
+        map.append_wall((-3.0, 2.0), (-3.0, -2.0));
+        map.append_obstacle(vec![(1.0, 0.5), (1.5, 0.5), (1.5, 1.0), (1.0, 1.0)]);
+        map
+    }
+
+    fn scan(pose: (f32, f32, f32)) -> Vec<(f32, f32)> {
+        let mut lidar = Lidar::new(room(), (0.1, 5.0), (-PI, PI), 180);
+        scan_points(lidar.data(pose), 5.0)
+    }
+
+    #[test]
+    fn test_align_both_methods() {
+        // from the origin, the pose of the second scan is where it was taken
+        let (a, truth) = ((0.0, 0.0, 0.0), (0.15, -0.1, 0.08));
+        for method in [IcpMethod::PointToPoint, IcpMethod::PointToLine].iter() {
+            let result = Icp::new(*method)
+                .set_iterations(100)
+                .align(&scan(a), &scan(truth), (0.0, 0.0, 0.0))
+                .unwrap();
+            assert!(result.converged, "{:?}", method);
+            assert!((result.pose.0 - truth.0).abs() < 0.02, "{:?}", method);
+            assert!((result.pose.1 - truth.1).abs() < 0.02, "{:?}", method);
+            assert!((result.pose.2 - truth.2).abs() < 0.01, "{:?}", method);
+            assert!(result.cov[[0, 0]] > 0.0 && result.cov[[2, 2]] > 0.0);
+        }
+        assert!(Icp::new(IcpMethod::PointToLine)
+            .align(&[(1.0, 0.0)], &scan(a), (0.0, 0.0, 0.0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_corridor_is_uncertain_along_it() {
+        let mut map = Map::new();
+        map.append_wall((-10.0, -1.0), (10.0, -1.0));
+        map.append_wall((-10.0, 1.0), (10.0, 1.0));
+        let mut lidar = Lidar::new(map, (0.1, 4.0), (-PI, PI), 180);
+        let reference = scan_points(lidar.data((0.0, 0.0, 0.0)), 4.0);
+        let current = scan_points(lidar.data((0.1, 0.0, 0.0)), 4.0);
+        let result = Icp::new(IcpMethod::PointToLine)
+            .align(&reference, &current, (0.1, 0.05, 0.0))
+            .unwrap();
+        assert!(result.pose.1.abs() < 0.01);
+        assert!(result.cov[[0, 0]] > 1e3 * result.cov[[1, 1]]);
+    }
+
+    #[test]
+    fn test_scan_odometry_against_wheel_drift() {
+        let mut world = World::new(room(), 4, 4, 10.0, 0.2).set_seed(2);
+        let icp = Icp::new(IcpMethod::PointToLine);
+        let odometry = ScanOdometry::new((-1.5, -1.0, 0.0), 5.0, motion_noise_cov(), icp);
+        let agent = EstimateAgent::new(0.2, 0.1, 0.2, odometry);
+        let lidar = Lidar::new(room(), (0.1, 5.0), (-PI, PI), 120);
+        let robot = Robot::new((-1.5, -1.0, 0.0), &BLACK, agent, lidar).set_bias((0.2, 0.2));
+        world.push(Box::new(robot));
+        world.run();
+
+        let robot = world
+            .object::<Robot<EstimateAgent<ScanOdometry>, Lidar>>(0)
+            .unwrap();
+        let estimator = &robot.agent.estimator;
+        let (x, y, _) = robot.pose;
+        let (ex, ey, _) = estimator.pose;
+        assert!(((x - ex).powi(2) + (y - ey).powi(2)).sqrt() < 0.15);
+        assert!(estimator.last_match.is_some());
+        assert!(estimator.cov[[0, 0]] > 0.0);
+
+        // dead reckoning with the commands drifts with the bias
+        let mut reckoned = (-1.5, -1.0, 0.0);
+        for _ in 0..robot.log.steps.len() {
+            reckoned = IdealRobot::<Agent, IdealCamera>::state_transition(0.2, 0.1, 0.2, reckoned);
+        }
+        let drift = ((x - reckoned.0).powi(2) + (y - reckoned.1).powi(2)).sqrt();
+        assert!(drift > ((x - ex).powi(2) + (y - ey).powi(2)).sqrt());
+    }
+
+    #[test]
+    fn test_scan_odometry_dead_reckons_without_scans() {
+        let icp = Icp::new(IcpMethod::PointToLine);
+        let mut odometry = ScanOdometry::new((0.0, 0.0, 0.0), 5.0, motion_noise_cov(), icp);
+        odometry.observation_update(&[]);
+        let mut reckoned = (0.0, 0.0, 0.0);
+        for _ in 0..3 {
+            odometry.motion_update(0.2, 0.1, 0.2);
+            odometry.observation_update(&[]);
+            reckoned = IdealRobot::<Agent, IdealCamera>::state_transition(0.2, 0.1, 0.2, reckoned);
+        }
+        assert!(odometry.last_match.is_none());
+        assert!((odometry.pose.0 - reckoned.0).abs() < 1e-5);
+        assert!((odometry.pose.1 - reckoned.1).abs() < 1e-5);
+        assert!((odometry.pose.2 - reckoned.2).abs() < 1e-5);
+        assert!(odometry.cov[[0, 0]] > 0.0 && odometry.cov[[2, 2]] > 0.0);
+    }
+}
@@ -0,0 +1,255 @@
+use ndarray::{arr1, arr2, Array2};
+use ndarray_linalg::Inverse;
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::plotters_ext::*;
+
+// Covariance of (nu, omega) after the noise of the 4x4 `motion_noise_cov`
+// (the one used by `Mcl`) is added to the velocities.
+pub fn mat_m(nu: f32, omega: f32, time: f32, motion_noise_cov: &Array2<f64>) -> Array2<f64> {
+    let a = (nu.abs() / time).sqrt() as f64;
+    let b = (omega.abs() / time).sqrt() as f64;
+    let mat_b = arr2(&[[a, b, 0.0, 0.0], [0.0, 0.0, a, b]]);
+    mat_b.dot(motion_noise_cov).dot(&mat_b.t())
+}
+
+// Jacobian of `IdealRobot::state_transition` with respect to (nu, omega)
+pub fn mat_a(nu: f32, omega: f32, time: f32, theta: f32) -> Array2<f64> {
+    let (nu, omega, time, theta) = (nu as f64, omega as f64, time as f64, theta as f64);
+    let st = theta.sin();
+    let ct = theta.cos();
+    let stw = (theta + omega * time).sin();
+    let ctw = (theta + omega * time).cos();
+    arr2(&[
+        [
+            (stw - st) / omega,
+            -nu / omega.powi(2) * (stw - st) + nu / omega * time * ctw,
+        ],
+        [
+            (-ctw + ct) / omega,
+            -nu / omega.powi(2) * (-ctw + ct) + nu / omega * time * stw,
+        ],
+        [0.0, time],
+    ])
+}
+
+// Jacobian of `IdealRobot::state_transition` with respect to the pose
+pub fn mat_f(nu: f32, omega: f32, time: f32, theta: f32) -> Array2<f64> {
+    let (nu, omega, time, theta) = (nu as f64, omega as f64, time as f64, theta as f64);
+    let mut f = Array2::<f64>::eye(3);
+    f[[0, 2]] = nu / omega * ((theta + omega * time).cos() - theta.cos());
+    f[[1, 2]] = nu / omega * ((theta + omega * time).sin() - theta.sin());
+    f
+}
+
+// Jacobian of `OpticalSensor::obs_fn` with respect to the camera pose
+pub fn mat_h(pose: (f32, f32, f32), landmark_pos: (f32, f32)) -> Array2<f64> {
+    let mx = (landmark_pos.0 - pose.0) as f64;
+    let my = (landmark_pos.1 - pose.1) as f64;
+    let q = mx.powi(2) + my.powi(2);
+    arr2(&[
+        [-mx / q.sqrt(), -my / q.sqrt(), 0.0],
+        [my / q, -mx / q, -1.0],
+    ])
+}
+
+pub fn mat_q(distance_dev: f32, direction_dev: f32) -> Array2<f64> {
+    Array2::from_diag(&arr1(&[
+        (distance_dev as f64).powi(2),
+        (direction_dev as f64).powi(2),
+    ]))
+}
+
+// Extended Kalman Filter localization
+#[derive(Clone)]
+pub struct KalmanFilter {
+    pub pose: (f32, f32, f32),
+    pub cov: Array2<f64>,
+    pub map: Map,
+    pub motion_noise_cov: Array2<f64>,
+    pub distance_dev_rate: f32,
+    pub direction_dev: f32,
+    pub innovations: Vec<Innovation>,
+}
+
+impl KalmanFilter {
+    pub fn new(map: Map, init_pose: (f32, f32, f32), motion_noise_cov: Array2<f64>) -> Self {
+        KalmanFilter {
+            pose: init_pose,
+            cov: Array2::from_diag(&arr1(&[1e-10, 1e-10, 1e-10])),
+            map,
+            motion_noise_cov,
+            distance_dev_rate: 0.14,
+            direction_dev: 0.05,
+            innovations: Vec::new(),
+        }
+    }
+
+    pub fn set_observation_noise(mut self, distance_dev_rate: f32, direction_dev: f32) -> Self {
+        self.distance_dev_rate = distance_dev_rate;
+        self.direction_dev = direction_dev;
+        self
+    }
+}
+
+impl Estimator for KalmanFilter {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        // The jacobians are singular at omega = 0
+        let omega = if omega.abs() < 1e-5 { 1e-5 } else { omega };
+        let m = mat_m(nu, omega, time, &self.motion_noise_cov);
+        let a = mat_a(nu, omega, time, self.pose.2);
+        let f = mat_f(nu, omega, time, self.pose.2);
+        self.cov = f.dot(&self.cov).dot(&f.t()) + a.dot(&m).dot(&a.t());
+        self.pose = IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, self.pose);
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        self.innovations.clear();
+        for obs in observation.iter() {
+            let landmark_pos = match self.map.landmarks.iter().find(|l| l.id == obs.id) {
+                Some(l) => l.position,
+                None => continue,
+            };
+            let estimated = IdealCamera::obs_fn(self.pose, landmark_pos);
+            let h = mat_h(self.pose, landmark_pos);
+            let q = mat_q(estimated.0 * self.distance_dev_rate, self.direction_dev);
+            let s = q + h.dot(&self.cov).dot(&h.t());
+            // nothing to learn from an observation without any uncertainty
+            let s_inv = match s.inv() {
+                Ok(s_inv) => s_inv,
+                Err(_) => continue,
+            };
+            let k = self.cov.dot(&h.t()).dot(&s_inv);
+
+            let innovation = arr1(&[
+                (obs.pos.0 - estimated.0) as f64,
+                normalize_angle(obs.pos.1 - estimated.1) as f64,
+            ]);
+            let correction = k.dot(&innovation);
+            self.innovations.push(Innovation {
+                value: innovation,
+                cov: s,
+            });
+            self.pose = (
+                self.pose.0 + correction[0] as f32,
+                self.pose.1 + correction[1] as f32,
+                normalize_angle(self.pose.2 + correction[2] as f32),
+            );
+            self.cov = (Array2::<f64>::eye(3) - k.dot(&h)).dot(&self.cov);
+        }
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        self.pose
+    }
+
+    fn pose_cov(&self) -> Option<Array2<f64>> {
+        Some(self.cov.clone())
+    }
+
+    fn innovations(&self) -> Vec<Innovation> {
+        self.innovations.clone()
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let (x, y, t) = self.pose;
+        let from = translate_coord(drawing_area, x, y, xlim, ylim);
+        let to = (
+            from.0 + (20.0 * t.cos()) as i32,
+            from.1 + (20.0 * -t.sin()) as i32,
+        );
+
+        let coord_spec = drawing_area.strip_coord_spec();
+        coord_spec
+            .draw(&Quiver::new(from, to, Into::<ShapeStyle>::into(&BLUE)))
+            .unwrap();
+
+        // about 3 sigma
+        let ellipse = ErrorEllipse::new((x, y), &self.cov, 0.99, BLUE)
+            .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+        coord_spec.draw(&ellipse).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_jacobians_match_finite_difference() {
+        let (nu, omega, time) = (0.2, 0.3, 0.1);
+        let pose = (0.5, -0.3, 0.7);
+        let eps = 1e-3;
+        let transition =
+            |n: f32, o: f32, p| IdealRobot::<Agent, IdealCamera>::state_transition(n, o, time, p);
+
+        let f = mat_f(nu, omega, time, pose.2);
+        let moved = transition(nu, omega, (pose.0, pose.1, pose.2 + eps));
+        let base = transition(nu, omega, pose);
+        assert!((f[[0, 2]] as f32 - (moved.0 - base.0) / eps).abs() < 1e-3);
+        assert!((f[[1, 2]] as f32 - (moved.1 - base.1) / eps).abs() < 1e-3);
+
+        let a = mat_a(nu, omega, time, pose.2);
+        let moved = transition(nu + eps, omega, pose);
+        assert!((a[[0, 0]] as f32 - (moved.0 - base.0) / eps).abs() < 1e-3);
+        assert!((a[[1, 0]] as f32 - (moved.1 - base.1) / eps).abs() < 1e-3);
+
+        let h = mat_h(pose, (3.0, 3.0));
+        let z = IdealCamera::obs_fn(pose, (3.0, 3.0));
+        let moved = IdealCamera::obs_fn((pose.0 + eps, pose.1, pose.2), (3.0, 3.0));
+        assert!((h[[0, 0]] as f32 - (moved.0 - z.0) / eps).abs() < 1e-2);
+        assert!((h[[1, 0]] as f32 - (moved.1 - z.1) / eps).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_motion_update_grows_covariance() {
+        let mut kf = KalmanFilter::new(landmarks(), (0.0, 0.0, 0.0), motion_noise_cov());
+        for _ in 0..10 {
+            kf.motion_update(0.2, 10.0 / 180.0 * PI, 0.1);
+        }
+        assert!(kf.cov[[0, 0]] > 1e-10);
+        assert!(kf.cov[[2, 2]] > 1e-10);
+        assert!(kf.pose.0 > 0.19 && kf.pose.0 < 0.21);
+    }
+
+    #[test]
+    fn test_observation_update_corrects_pose() {
+        let map = landmarks();
+        let true_pose = (0.0, 0.0, PI / 4.0);
+        let mut kf = KalmanFilter::new(map.clone(), (0.2, -0.2, PI / 4.0), motion_noise_cov());
+        kf.cov = Array2::from_diag(&arr1(&[0.1, 0.1, 0.01]));
+
+        let obs = map
+            .landmarks
+            .iter()
+            .map(|l| Observation::new(IdealCamera::obs_fn(true_pose, l.position), l.id))
+            .collect::<Vec<Observation>>();
+        kf.observation_update(&obs);
+
+        assert!(kf.pose.0.abs() < 0.1);
+        assert!(kf.pose.1.abs() < 0.1);
+        assert!(kf.cov[[0, 0]] < 0.1);
+    }
+
+    #[test]
+    fn test_singular_innovation_cov_is_skipped() {
+        let map = landmarks();
+        let mut kf = KalmanFilter::new(map.clone(), (0.2, -0.2, PI / 4.0), motion_noise_cov())
+            .set_observation_noise(0.0, 0.0);
+        kf.cov = Array2::zeros((3, 3));
+
+        let obs = vec![Observation::new((1.0, 0.0), 2)];
+        kf.observation_update(&obs);
+
+        assert_eq!(kf.pose, (0.2, -0.2, PI / 4.0));
+        assert!(kf.innovations.is_empty());
+    }
+}
@@ -1,11 +1,47 @@
 pub mod base;
+pub mod beam_model;
+pub mod collision;
+pub mod ekf_slam;
+pub mod experiment;
+pub mod fast_slam;
+pub mod geometry;
+pub mod graph_slam;
+pub mod icp;
+pub mod kf;
+pub mod lidar;
+pub mod likelihood_field;
+pub mod log;
+pub mod mcl;
+pub mod metrics;
 pub mod mvtnorm;
+pub mod occupancy_grid;
 pub mod plotters_ext;
+pub mod rng;
 pub mod robot;
+pub mod scenario;
+#[cfg(test)]
+pub(crate) mod test_util;
 
 pub mod prelude {
     pub use crate::base::*;
+    pub use crate::beam_model::*;
+    pub use crate::collision::*;
+    pub use crate::ekf_slam::*;
+    pub use crate::experiment::*;
+    pub use crate::fast_slam::*;
+    pub use crate::geometry::*;
+    pub use crate::graph_slam::*;
+    pub use crate::icp::*;
+    pub use crate::kf::*;
+    pub use crate::lidar::*;
+    pub use crate::likelihood_field::*;
+    pub use crate::log::*;
+    pub use crate::mcl::*;
+    pub use crate::metrics::*;
     pub use crate::mvtnorm::*;
+    pub use crate::occupancy_grid::*;
     pub use crate::plotters_ext::*;
+    pub use crate::rng::*;
     pub use crate::robot::*;
+    pub use crate::scenario::*;
 }
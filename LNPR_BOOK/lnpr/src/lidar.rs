@@ -0,0 +1,263 @@
+use plotters::prelude::*;
+use std::f32::consts::PI;
+
+use crate::base::*;
+use crate::beam_model::*;
+use crate::collision::*;
+use crate::geometry::*;
+use crate::rng::SimRng;
+
+// 2D laser range finder. Each reading is an `Observation` of (distance,
+// direction) whose id is the index of the beam. Beams that hit nothing, or
+// hit nearer than the minimum range, read the maximum range. Readings are
+// exact unless a beam model is set.
+#[derive(Clone)]
+pub struct Lidar {
+    pub map: Map,
+    pub lastdata: Vec<Observation>,
+    pub distance_range: (f32, f32),
+    pub direction_range: (f32, f32),
+    pub beams: usize,
+    // bodies of the other robots, which reflect the beams as walls do
+    pub obstructions: Vec<Footprint>,
+    pub beam_model: Option<BeamModel>,
+    rng: SimRng,
+}
+
+impl Lidar {
+    pub fn new(
+        map: Map,
+        distance_range: (f32, f32),
+        direction_range: (f32, f32),
+        beams: usize,
+    ) -> Self {
+        Lidar {
+            map,
+            lastdata: Vec::new(),
+            distance_range,
+            direction_range,
+            beams,
+            obstructions: Vec::new(),
+            beam_model: None,
+            rng: SimRng::default(),
+        }
+    }
+
+    pub fn set_rng(mut self, rng: SimRng) -> Self {
+        OpticalSensor::set_rng(&mut self, rng);
+        self
+    }
+
+    // Readings are drawn from `beam_model`, whose max range becomes the one
+    // of the lidar
+    pub fn set_beam_model(mut self, beam_model: BeamModel) -> Self {
+        self.beam_model = Some(BeamModel {
+            max_range: self.distance_range.1,
+            ..beam_model
+        });
+        self
+    }
+
+    // Directions of the beams relative to the heading, evenly spread over
+    // `direction_range`. A full turn does not repeat its first beam.
+    pub fn directions(&self) -> Vec<f32> {
+        let (from, to) = self.direction_range;
+        let span = to - from;
+        let gaps = if span >= 2.0 * PI - 1e-6 || self.beams < 2 {
+            self.beams
+        } else {
+            self.beams - 1
+        };
+        let step = if gaps == 0 { 0.0 } else { span / gaps as f32 };
+        (0..self.beams).map(|i| from + step * i as f32).collect()
+    }
+
+    // True distance along a beam from `cam_pose`, or None if nothing is within
+    // the maximum range
+    pub fn ray_cast(&self, cam_pose: (f32, f32, f32), direction: f32) -> Option<f32> {
+        let origin = (cam_pose.0, cam_pose.1);
+        let heading = cam_pose.2 + direction;
+        let max = self.distance_range.1;
+        let wall = self.map.ray_cast(origin, heading, max);
+        self.obstructions
+            .iter()
+            .filter_map(|o| ray_circle_intersection(origin, heading, max, o.position, o.radius))
+            .fold(wall, |nearest: Option<f32>, d| {
+                Some(nearest.map_or(d, |n| n.min(d)))
+            })
+    }
+
+    pub fn is_max_range(&self, obs: &Observation) -> bool {
+        obs.pos.0 >= self.distance_range.1
+    }
+}
+
+impl OpticalSensor for Lidar {
+    fn map(&self) -> Map {
+        self.map.clone()
+    }
+
+    fn lastdata(&self) -> Vec<Observation> {
+        self.lastdata.clone()
+    }
+
+    fn distance_range(&self) -> (f32, f32) {
+        self.distance_range
+    }
+
+    fn direction_range(&self) -> (f32, f32) {
+        self.direction_range
+    }
+
+    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<Observation> {
+        let (min, max) = self.distance_range;
+        let mut rng = self.rng.clone();
+        let scan = self
+            .directions()
+            .iter()
+            .enumerate()
+            .map(|(i, &direction)| {
+                let mut distance = self.ray_cast(cam_pose, direction).unwrap_or(max);
+                if let Some(model) = self.beam_model {
+                    distance = model.sample(distance, &mut rng);
+                }
+                if distance < min {
+                    distance = max;
+                }
+                Observation::new((distance, direction), i as i32)
+            })
+            .collect();
+        self.lastdata = scan;
+        &self.lastdata
+    }
+
+    fn set_obstructions(&mut self, obstructions: &[Footprint]) {
+        self.obstructions = obstructions.to_vec();
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.rng = rng;
+    }
+
+    fn obs_fn(cam_pose: (f32, f32, f32), obj_pos: (f32, f32)) -> (f32, f32) {
+        IdealCamera::obs_fn(cam_pose, obj_pos)
+    }
+
+    // The covered area and only the beams which hit something
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        cam_pose: (f32, f32, f32),
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        draw_field_of_view(self, cam_pose, drawing_area, xlim, ylim);
+        let (x, y, theta) = cam_pose;
+        let from = translate_coord(drawing_area, x, y, xlim, ylim);
+
+        let coord_spec = drawing_area.strip_coord_spec();
+        self.lastdata
+            .iter()
+            .filter(|obs| !self.is_max_range(obs))
+            .for_each(|obs| {
+                let (distance, direction) = obs.pos;
+                let hx = x + distance * (direction + theta).cos();
+                let hy = y + distance * (direction + theta).sin();
+                let to = translate_coord(drawing_area, hx, hy, xlim, ylim);
+                coord_spec
+                    .draw(&PathElement::new(vec![from, to], MAGENTA.mix(0.3)))
+                    .unwrap();
+                coord_spec
+                    .draw(&Circle::new(to, 2, MAGENTA.filled()))
+                    .unwrap();
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> Map {
+        let mut map = Map::new();
+        map.append_wall((2.0, -2.0), (2.0, 2.0));
+        map.append_wall((-2.0, -2.0), (-2.0, 2.0));
+        map
+    }
+
+    #[test]
+    fn test_lidar_directions() {
+        let lidar = Lidar::new(Map::new(), (0.1, 5.0), (-PI / 2.0, PI / 2.0), 5);
+        let directions = lidar.directions();
+        assert_eq!(directions.len(), 5);
+        assert!((directions[0] + PI / 2.0).abs() < 1e-6);
+        assert!((directions[4] - PI / 2.0).abs() < 1e-6);
+
+        let lidar = Lidar::new(Map::new(), (0.1, 5.0), (-PI, PI), 4);
+        let directions = lidar.directions();
+        assert!((directions[3] - PI / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_lidar_scan() {
+        let mut lidar = Lidar::new(room(), (0.1, 5.0), (-PI, PI), 4);
+        let scan = lidar.data((0.5, 0.0, 0.0)).clone();
+        assert_eq!(scan.len(), 4);
+        // behind, right, ahead, left
+        assert!((scan[0].pos.0 - 2.5).abs() < 1e-5);
+        assert_eq!(scan[1].pos.0, 5.0);
+        assert!((scan[2].pos.0 - 1.5).abs() < 1e-5);
+        assert!(lidar.is_max_range(&scan[3]));
+        assert_eq!(scan[2].id, 2);
+
+        lidar.set_obstructions(&[Footprint {
+            id: 1,
+            position: (1.0, 0.0),
+            radius: 0.2,
+        }]);
+        let scan = lidar.data((0.5, 0.0, 0.0));
+        assert!((scan[2].pos.0 - 0.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_lidar_with_beam_model() {
+        let model = BeamModel::new(10.0)
+            .set_weights(0.5, 0.0, 0.5, 0.0)
+            .set_hit(0.0, 0.01);
+        let mut lidar = Lidar::new(room(), (0.1, 5.0), (-0.1, 0.1), 200)
+            .set_rng(SimRng::seed_from_u64(1))
+            .set_beam_model(model);
+        assert_eq!(lidar.beam_model.unwrap().max_range, 5.0);
+
+        let scan = lidar.data((0.0, 0.0, 0.0)).clone();
+        let misses = scan.iter().filter(|o| lidar.is_max_range(o)).count();
+        assert!(misses > 60 && misses < 140);
+        assert!(scan
+            .iter()
+            .filter(|o| !lidar.is_max_range(o))
+            .all(|o| (o.pos.0 - 2.0 / o.pos.1.cos()).abs() < 0.06));
+    }
+
+    #[test]
+    fn test_lidar_on_robot() {
+        let lidar = Lidar::new(room(), (0.1, 5.0), (-PI, PI), 36);
+        let mut world = World::new(room(), 5, 5, 1.0, 0.5);
+        let agent = Agent {
+            nu: 0.2,
+            omega: 0.0,
+        };
+        world.push(Box::new(IdealRobot::new(
+            (0.0, 0.0, 0.0),
+            &BLACK,
+            agent,
+            lidar,
+        )));
+        world.run();
+
+        let robot = world.object::<IdealRobot<Agent, Lidar>>(0).unwrap();
+        assert_eq!(robot.sensor.lastdata.len(), 36);
+        let ahead = robot.sensor.lastdata[18];
+        assert!(ahead.pos.1.abs() < 1e-6);
+        assert!(ahead.pos.0 < 2.0 && ahead.pos.0 > 1.5);
+    }
+}
@@ -0,0 +1,234 @@
+use ndarray::Array2;
+use std::f64::consts::PI;
+
+use crate::base::*;
+use crate::occupancy_grid::OccupancyGrid;
+
+// Distance from each cell to the nearest occupied one, for scoring range scans
+// by where their beams end rather than by ray casting. Covers
+// [-xlim, xlim) x [-ylim, ylim) as the occupancy grid does.
+#[derive(Clone, Debug)]
+pub struct LikelihoodField {
+    pub distances: Array2<f32>,
+    pub cell: f32,
+    pub xlim: i32,
+    pub ylim: i32,
+    // readings at or beyond it hit nothing and are not scored
+    pub max_range: f32,
+    // spread of the beam ends around the nearest obstacle
+    pub hit_std: f32,
+    // weights of a hit and of a random reading
+    pub z_hit: f32,
+    pub z_rand: f32,
+}
+
+impl LikelihoodField {
+    fn with_distances(
+        distances: Array2<f32>,
+        cell: f32,
+        xlim: i32,
+        ylim: i32,
+        max_range: f32,
+    ) -> Self {
+        LikelihoodField {
+            distances,
+            cell,
+            xlim,
+            ylim,
+            max_range,
+            hit_std: 0.1,
+            z_hit: 0.9,
+            z_rand: 0.1,
+        }
+    }
+
+    // Distances to the walls and obstacles of `map` from the cell centers.
+    // Cells are as far as the larger of the world sizes from a map without any.
+    pub fn from_map(map: &Map, xlim: i32, ylim: i32, cell: f32, max_range: f32) -> Self {
+        let nx = (2.0 * xlim as f32 / cell).ceil() as usize;
+        let ny = (2.0 * ylim as f32 / cell).ceil() as usize;
+        let far = 2.0 * xlim.max(ylim) as f32;
+        let distances = Array2::from_shape_fn((nx, ny), |(i, j)| {
+            let x = -xlim as f32 + (i as f32 + 0.5) * cell;
+            let y = -ylim as f32 + (j as f32 + 0.5) * cell;
+            map.distance_to_nearest((x, y)).unwrap_or(far)
+        });
+        Self::with_distances(distances, cell, xlim, ylim, max_range)
+    }
+
+    // Distances to the occupied cells of `grid`, propagated from neighbor to
+    // neighbor in a forward and a backward pass. Each cell keeps the nearest
+    // occupied cell of its neighbors, which is close to the exact distance.
+    pub fn from_grid(grid: &OccupancyGrid) -> Self {
+        let (nx, ny) = grid.log_odds.dim();
+        let mut nearest: Array2<Option<(usize, usize)>> =
+            Array2::from_shape_fn((nx, ny), |(i, j)| {
+                if grid.is_occupied(i, j) {
+                    Some((i, j))
+                } else {
+                    None
+                }
+            });
+        let distance2 = |a: (usize, usize), b: (usize, usize)| {
+            let (di, dj) = (a.0 as f32 - b.0 as f32, a.1 as f32 - b.1 as f32);
+            di * di + dj * dj
+        };
+        let mut relax = |i: usize, j: usize, neighbors: &[(isize, isize)]| {
+            for &(di, dj) in neighbors.iter() {
+                let (ni, nj) = (i as isize + di, j as isize + dj);
+                if ni < 0 || nj < 0 || ni as usize >= nx || nj as usize >= ny {
+                    continue;
+                }
+                if let Some(candidate) = nearest[[ni as usize, nj as usize]] {
+                    let closer = match nearest[[i, j]] {
+                        Some(current) => distance2((i, j), candidate) < distance2((i, j), current),
+                        None => true,
+                    };
+                    if closer {
+                        nearest[[i, j]] = Some(candidate);
+                    }
+                }
+            }
+        };
+        let forward = [(-1, -1), (-1, 0), (-1, 1), (0, -1)];
+        let backward = [(1, 1), (1, 0), (1, -1), (0, 1)];
+        for i in 0..nx {
+            for j in 0..ny {
+                relax(i, j, &forward);
+            }
+        }
+        for i in (0..nx).rev() {
+            for j in (0..ny).rev() {
+                relax(i, j, &backward);
+            }
+        }
+
+        let far = 2.0 * grid.xlim.max(grid.ylim) as f32;
+        let distances = Array2::from_shape_fn((nx, ny), |(i, j)| match nearest[[i, j]] {
+            Some(c) => distance2((i, j), c).sqrt() * grid.cell,
+            None => far,
+        });
+        Self::with_distances(distances, grid.cell, grid.xlim, grid.ylim, grid.max_range)
+    }
+
+    pub fn set_noise(mut self, hit_std: f32, z_hit: f32, z_rand: f32) -> Self {
+        self.hit_std = hit_std;
+        self.z_hit = z_hit;
+        self.z_rand = z_rand;
+        self
+    }
+
+    // Distance to the nearest obstacle from the cell `point` is in, None
+    // outside the field
+    pub fn distance(&self, point: (f32, f32)) -> Option<f32> {
+        let i = ((point.0 + self.xlim as f32) / self.cell).floor();
+        let j = ((point.1 + self.ylim as f32) / self.cell).floor();
+        let (nx, ny) = self.distances.dim();
+        if i >= 0.0 && j >= 0.0 && (i as usize) < nx && (j as usize) < ny {
+            Some(self.distances[[i as usize, j as usize]])
+        } else {
+            None
+        }
+    }
+
+    // Sum of the log likelihoods of the beams of `scan` from `pose`. A beam
+    // ending off the field counts as a random reading.
+    pub fn scan_log_likelihood(&self, pose: (f32, f32, f32), scan: &[Observation]) -> f64 {
+        let (x, y, theta) = pose;
+        let std = self.hit_std as f64;
+        let random = self.z_rand as f64 / self.max_range as f64;
+        scan.iter()
+            .filter(|obs| obs.pos.0 < self.max_range)
+            .map(|obs| {
+                let (distance, direction) = obs.pos;
+                let end = (
+                    x + distance * (theta + direction).cos(),
+                    y + distance * (theta + direction).sin(),
+                );
+                let hit = self.distance(end).map_or(0.0, |d| {
+                    let e = d as f64 / std;
+                    (-0.5 * e * e).exp() / (std * (2.0 * PI).sqrt())
+                });
+                (self.z_hit as f64 * hit + random).ln()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::*;
+    use crate::mcl::*;
+    use ndarray::arr2;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    fn corridor() -> Map {
+        let mut map = Map::new();
+        map.append_wall((-4.0, -1.0), (4.0, -1.0));
+        map.append_wall((-4.0, 1.0), (4.0, 1.0));
+        map.append_wall((4.0, -1.0), (4.0, 1.0));
+        map.append_obstacle(vec![(0.5, 0.6), (1.0, 0.6), (1.0, 1.0), (0.5, 1.0)]);
+        map
+    }
+
+    #[test]
+    fn test_field_from_map_and_grid() {
+        let field = LikelihoodField::from_map(&corridor(), 5, 5, 0.1, 5.0);
+        assert!((field.distance((-2.0, 0.05)).unwrap() - 0.95).abs() < 1e-4);
+        assert_eq!(field.distance((6.0, 0.0)), None);
+
+        let mut grid = OccupancyGrid::new(5, 5, 0.1, 5.0);
+        let wall = grid.index(2.05, 0.05).unwrap();
+        grid.log_odds[[wall.0, wall.1]] = 1.0;
+        let field = LikelihoodField::from_grid(&grid);
+        assert_eq!(field.distance((2.05, 0.05)), Some(0.0));
+        assert!((field.distance((1.05, 0.05)).unwrap() - 1.0).abs() < 1e-4);
+        assert!((field.distance((2.35, 0.45)).unwrap() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_scan_log_likelihood() {
+        let map = corridor();
+        let field = LikelihoodField::from_map(&map, 5, 5, 0.05, 5.0);
+        let pose = (-1.0, 0.0, 0.0);
+        let scan = Lidar::new(map, (0.1, 5.0), (-PI, PI), 90)
+            .data(pose)
+            .clone();
+        let right = field.scan_log_likelihood(pose, &scan);
+        let shifted = field.scan_log_likelihood((-1.0, 0.3, 0.0), &scan);
+        let turned = field.scan_log_likelihood((-1.0, 0.0, 0.3), &scan);
+        assert!(right > shifted && right > turned);
+    }
+
+    #[test]
+    fn test_scan_mcl_in_corridor() {
+        let map = corridor();
+        let field = LikelihoodField::from_map(&map, 5, 5, 0.05, 5.0).set_noise(0.2, 0.9, 0.1);
+        let cov = arr2(&[
+            [0.1f64.powi(2), 0.0, 0.0, 0.0],
+            [0.0, 0.01f64.powi(2), 0.0, 0.0],
+            [0.0, 0.0, 0.01f64.powi(2), 0.0],
+            [0.0, 0.0, 0.0, 0.1f64.powi(2)],
+        ]);
+        let mut world = World::new(map.clone(), 5, 5, 20.0, 0.2).set_seed(5);
+        let mcl = Mcl::new(map.clone(), (-3.0, 0.0, 0.0), 100, cov).set_likelihood_field(field);
+        let agent = EstimateAgent::new(0.2, 0.0, 0.2, mcl);
+        let lidar = Lidar::new(map, (0.1, 5.0), (-PI, PI), 60);
+        world.push(Box::new(IdealRobot::new(
+            (-3.0, 0.0, 0.0),
+            &BLACK,
+            agent,
+            lidar,
+        )));
+        world.run();
+
+        let robot = world
+            .object::<IdealRobot<EstimateAgent<Mcl>, Lidar>>(0)
+            .unwrap();
+        let (x, y, _) = robot.pose;
+        let (ex, ey, _) = robot.agent.estimator.mean_pose();
+        assert!(((x - ex).powi(2) + (y - ey).powi(2)).sqrt() < 0.3);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::base::*;
+use crate::collision::*;
+
+// What a `Robot` went through in one time step
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogStep {
+    // true pose when the camera took its picture
+    pub pose: (f32, f32, f32),
+    // (nu, omega) decided by the agent
+    pub command: (f32, f32),
+    // (nu, omega) actually applied after bias and getting stuck
+    pub velocity: (f32, f32),
+    // readings given to the agent, phantoms included
+    pub observations: Vec<Observation>,
+    // visible landmarks the camera failed to see
+    pub overlooked: Vec<Observation>,
+    // what the robot ran into at the end of the step, see `World::set_collision`
+    pub collisions: Vec<Collision>,
+}
+
+// Recording of a run, kept as a text file with one record per line:
+//
+//     interval 0.1
+//     step x y theta command_nu command_omega nu omega
+//     obs id distance direction is_phantom
+//     overlooked id distance direction is_phantom
+//     collision obstacle|bound|robot N x y theta
+//
+// `obs`, `overlooked` and `collision` lines belong to the `step` above them.
+// The pose of a collision is where the robot was left after it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Log {
+    pub time_interval: f32,
+    pub steps: Vec<LogStep>,
+}
+
+impl Log {
+    pub fn new(time_interval: f32) -> Self {
+        Log {
+            time_interval,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LogError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LogError> {
+        Ok(std::fs::write(path, self.to_string())?)
+    }
+
+    // True poses at every step
+    pub fn poses(&self) -> Vec<(f32, f32, f32)> {
+        self.steps.iter().map(|s| s.pose).collect()
+    }
+
+    // Feeds the recorded commands and observations to `estimator` in the same
+    // order as `EstimateAgent` does, without simulating anything. Returns the
+    // initial estimate followed by the estimate after every step.
+    pub fn replay<E: Estimator>(&self, estimator: &mut E) -> Vec<(f32, f32, f32)> {
+        self.replay_with(estimator, |e| e.pose())
+    }
+
+    // Same as `replay`, taking what `f` reads from the estimator at every step
+    pub fn replay_with<E: Estimator, T, F: Fn(&E) -> T>(&self, estimator: &mut E, f: F) -> Vec<T> {
+        let mut outputs = vec![f(estimator)];
+        let mut prev = (0.0, 0.0);
+        for step in self.steps.iter() {
+            estimator.motion_update(prev.0, prev.1, self.time_interval);
+            estimator.observation_update(&step.observations);
+            prev = step.command;
+            outputs.push(f(estimator));
+        }
+        outputs
+    }
+}
+
+fn write_observation(f: &mut fmt::Formatter, tag: &str, o: &Observation) -> fmt::Result {
+    writeln!(
+        f,
+        "{} {} {} {} {}",
+        tag, o.id, o.pos.0, o.pos.1, o.is_phantom as i32
+    )
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "interval {}", self.time_interval)?;
+        for s in self.steps.iter() {
+            writeln!(
+                f,
+                "step {} {} {} {} {} {} {}",
+                s.pose.0, s.pose.1, s.pose.2, s.command.0, s.command.1, s.velocity.0, s.velocity.1
+            )?;
+            for o in s.observations.iter() {
+                write_observation(f, "obs", o)?;
+            }
+            for o in s.overlooked.iter() {
+                write_observation(f, "overlooked", o)?;
+            }
+            for c in s.collisions.iter() {
+                let (x, y, theta) = c.pose;
+                match c.contact {
+                    Contact::Obstacle => write!(f, "collision obstacle")?,
+                    Contact::Bound => write!(f, "collision bound")?,
+                    Contact::Robot(id) => write!(f, "collision robot {}", id)?,
+                }
+                writeln!(f, " {} {} {}", x, y, theta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "cannot access log: {}", e),
+            LogError::Parse { line, message } => {
+                write!(f, "invalid log at line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+impl From<std::io::Error> for LogError {
+    fn from(e: std::io::Error) -> Self {
+        LogError::Io(e)
+    }
+}
+
+fn parse_fields<T: FromStr>(fields: &[&str], count: usize) -> Result<Vec<T>, String> {
+    if fields.len() != count {
+        return Err(format!("expected {} values, found {}", count, fields.len()));
+    }
+    fields
+        .iter()
+        .map(|v| v.parse().map_err(|_| format!("bad value: {}", v)))
+        .collect()
+}
+
+fn parse_observation(fields: &[&str]) -> Result<Observation, String> {
+    let v = parse_fields::<f32>(fields, 4)?;
+    Ok(Observation {
+        pos: (v[1], v[2]),
+        id: v[0] as i32,
+        is_phantom: v[3] != 0.0,
+    })
+}
+
+fn parse_collision(fields: &[&str]) -> Result<Collision, String> {
+    let (contact, pose) = match fields.split_first() {
+        Some((&"obstacle", pose)) => (Contact::Obstacle, pose),
+        Some((&"bound", pose)) => (Contact::Bound, pose),
+        Some((&"robot", rest)) if !rest.is_empty() => {
+            let id = rest[0]
+                .parse()
+                .map_err(|_| format!("bad robot: {}", rest[0]))?;
+            (Contact::Robot(id), &rest[1..])
+        }
+        _ => return Err("unknown contact".into()),
+    };
+    let v = parse_fields::<f32>(pose, 3)?;
+    Ok(Collision {
+        contact,
+        pose: (v[0], v[1], v[2]),
+    })
+}
+
+impl FromStr for Log {
+    type Err = LogError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut log = Log::default();
+        for (i, line) in s.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (tag, values) = match fields.split_first() {
+                Some((tag, values)) => (*tag, values),
+                None => continue,
+            };
+            let last = log.steps.last_mut();
+            let result = match (tag, last) {
+                ("interval", _) => parse_fields(values, 1).map(|v| log.time_interval = v[0]),
+                ("step", _) => parse_fields(values, 7).map(|v| {
+                    log.steps.push(LogStep {
+                        pose: (v[0], v[1], v[2]),
+                        command: (v[3], v[4]),
+                        velocity: (v[5], v[6]),
+                        observations: Vec::new(),
+                        overlooked: Vec::new(),
+                        collisions: Vec::new(),
+                    })
+                }),
+                ("obs", Some(step)) => parse_observation(values).map(|o| step.observations.push(o)),
+                ("overlooked", Some(step)) => {
+                    parse_observation(values).map(|o| step.overlooked.push(o))
+                }
+                ("collision", Some(step)) => {
+                    parse_collision(values).map(|c| step.collisions.push(c))
+                }
+                ("obs", None) | ("overlooked", None) => Err("observation before any step".into()),
+                ("collision", None) => Err("collision before any step".into()),
+                _ => Err(format!("unknown record: {}", tag)),
+            };
+            result.map_err(|message| LogError::Parse {
+                line: i + 1,
+                message,
+            })?;
+        }
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kf::*;
+    use crate::robot::*;
+    use crate::test_util::*;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    fn kf(map: &Map) -> KalmanFilter {
+        KalmanFilter::new(map.clone(), (0.0, 0.0, 0.0), motion_noise_cov())
+    }
+
+    #[test]
+    fn test_log_roundtrip() {
+        let mut log = Log::new(0.1);
+        log.steps.push(LogStep {
+            pose: (0.1, -2.0, 0.3),
+            command: (0.2, 0.17453292),
+            velocity: (0.0, 0.0),
+            observations: vec![Observation {
+                pos: (1.5, -0.25),
+                id: 2,
+                is_phantom: true,
+            }],
+            overlooked: vec![Observation::new((3.25, 0.5), 0)],
+            collisions: vec![
+                Collision {
+                    contact: Contact::Robot(3),
+                    pose: (0.1, -1.98, 0.35),
+                },
+                Collision {
+                    contact: Contact::Bound,
+                    pose: (0.1, -1.98, 0.35),
+                },
+            ],
+        });
+        assert_eq!(log.to_string().parse::<Log>().unwrap(), log);
+
+        let orphan = "interval 0.1\nobs 0 1.0 0.0 0".parse::<Log>();
+        assert!(matches!(orphan, Err(LogError::Parse { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_replay_matches_simulation() {
+        let map = landmarks();
+        let mut world = World::new(map.clone(), 5, 5, 3.0, 0.1).set_seed(5);
+        let agent = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, kf(&map));
+        let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0))
+            .set_phantom(0.2, (-5.0, 5.0), (-5.0, 5.0))
+            .set_oversight(0.3);
+        let robot = Robot::new((0.0, 0.0, 0.0), &BLACK, agent, camera).set_stuck(1.0, 0.5);
+        world.push(Box::new(robot));
+        world.run();
+
+        let robot = world
+            .object::<Robot<EstimateAgent<KalmanFilter>, Camera>>(0)
+            .unwrap();
+        let log = robot.log.to_string().parse::<Log>().unwrap();
+        assert_eq!(log.steps.len(), world.max_iteration() as usize);
+        assert!(log.steps.iter().any(|s| !s.overlooked.is_empty()));
+        assert!(log.steps.iter().any(|s| s.velocity == (0.0, 0.0)));
+        assert!(log
+            .steps
+            .iter()
+            .all(|s| s.command == (0.2, 10.0 / 180.0 * PI)));
+
+        let poses = log.replay(&mut kf(&map));
+        assert_eq!(poses.last(), Some(&robot.agent.estimator.pose()));
+    }
+}
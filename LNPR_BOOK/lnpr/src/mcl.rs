@@ -0,0 +1,579 @@
+use std::f32::consts::PI;
+
+use ndarray::{Array1, Array2};
+use plotters::prelude::*;
+use rand::Rng;
+use rand_distr::{Distribution, Uniform};
+
+use crate::base::*;
+use crate::likelihood_field::LikelihoodField;
+use crate::mvtnorm::*;
+use crate::plotters_ext::*;
+use crate::rng::*;
+
+// Particle
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub pose: (f32, f32, f32),
+    pub weight: f32,
+}
+
+impl Particle {
+    pub fn new(init_pose: (f32, f32, f32), weight: f32) -> Self {
+        Particle {
+            pose: init_pose,
+            weight,
+        }
+    }
+
+    pub fn motion_update<R: ndarray_rand::rand::RngCore>(
+        &mut self,
+        nu: f32,
+        omega: f32,
+        time: f32,
+        motion_noise_cov: &Array2<f64>,
+        rng: &mut R,
+    ) {
+        let mu: Array1<f64> = Array1::from(vec![0.0, 0.0, 0.0, 0.0]);
+        let ns = mvtnorm(rng, &mu, motion_noise_cov);
+        let noised_nu = nu
+            + ns[0] as f32 * (nu.abs() / time).sqrt()
+            + ns[1] as f32 * (omega.abs() / time).sqrt();
+        let noised_omega = omega
+            + ns[2] as f32 * (nu.abs() / time).sqrt()
+            + ns[3] as f32 * (omega.abs() / time).sqrt();
+        self.pose = IdealRobot::<Agent, IdealCamera>::state_transition(
+            noised_nu,
+            noised_omega,
+            time,
+            self.pose,
+        );
+    }
+
+    pub fn observation_update(
+        &mut self,
+        observation: &[Observation],
+        map: &Map,
+        distance_dev_rate: f32,
+        direction_dev: f32,
+    ) {
+        for obs in observation.iter() {
+            let landmark = match map.landmarks.iter().find(|l| l.id == obs.id) {
+                Some(l) => l,
+                None => continue,
+            };
+            let suggest = IdealCamera::obs_fn(self.pose, landmark.position);
+            self.weight *=
+                observation_likelihood(obs.pos, suggest, distance_dev_rate, direction_dev);
+        }
+    }
+}
+
+// Density of `obs` under a gaussian centered on the observation `suggest`
+// which the particle expects to get.
+pub fn observation_likelihood(
+    obs: (f32, f32),
+    suggest: (f32, f32),
+    distance_dev_rate: f32,
+    direction_dev: f32,
+) -> f32 {
+    // kept above zero for a landmark the particle is right on
+    let distance_dev = (suggest.0 * distance_dev_rate).max(1e-3);
+    let ell = (obs.0 - suggest.0) / distance_dev;
+    let phi = normalize_angle(obs.1 - suggest.1) / direction_dev;
+    (-0.5 * (ell.powi(2) + phi.powi(2))).exp() / (2.0 * PI * distance_dev * direction_dev)
+}
+
+// Indices picked by systematic resampling from normalized weights
+pub fn systematic_sampling<R: Rng>(weights: &[f32], rng: &mut R) -> Vec<usize> {
+    let num = weights.len();
+    if num == 0 {
+        return Vec::new();
+    }
+    let step = 1.0 / num as f32;
+    let mut r = Uniform::from(0.0..step).sample(rng);
+
+    let mut picked = Vec::with_capacity(num);
+    let mut cur = 0;
+    let mut accum = weights[0];
+    while picked.len() < num {
+        if r < accum || cur == num - 1 {
+            picked.push(cur);
+            r += step;
+        } else {
+            cur += 1;
+            accum += weights[cur];
+        }
+    }
+    picked
+}
+
+// Monte Carlo Localization
+#[derive(Clone)]
+pub struct Mcl {
+    pub particles: Vec<Particle>,
+    pub map: Map,
+    pub motion_noise_cov: Array2<f64>,
+    pub distance_dev_rate: f32,
+    pub direction_dev: f32,
+    pub pose: (f32, f32, f32),
+    // cell size of the belief heatmap, not drawn if None
+    pub heatmap_cell: Option<f32>,
+    // observations are range scans scored on it, instead of landmarks
+    pub likelihood_field: Option<LikelihoodField>,
+    // the particles with their normalized weights before the last resampling,
+    // which are drawn; empty after a motion update
+    pub weighted: Vec<Particle>,
+    rng: SimRng,
+}
+
+impl Mcl {
+    pub fn new(
+        map: Map,
+        init_pose: (f32, f32, f32),
+        num: usize,
+        motion_noise_cov: Array2<f64>,
+    ) -> Self {
+        let mut particles = Vec::with_capacity(num);
+        for _ in 0..num {
+            particles.push(Particle::new(init_pose, 1.0 / num as f32));
+        }
+
+        Mcl {
+            particles,
+            map,
+            motion_noise_cov,
+            distance_dev_rate: 0.14,
+            direction_dev: 0.05,
+            pose: init_pose,
+            heatmap_cell: None,
+            likelihood_field: None,
+            weighted: Vec::new(),
+            rng: SimRng::default(),
+        }
+    }
+
+    pub fn set_rng(mut self, rng: SimRng) -> Self {
+        Estimator::set_rng(&mut self, rng);
+        self
+    }
+
+    pub fn set_observation_noise(mut self, distance_dev_rate: f32, direction_dev: f32) -> Self {
+        self.distance_dev_rate = distance_dev_rate;
+        self.direction_dev = direction_dev;
+        self
+    }
+
+    // Draws the histogram of the particles over cells of `cell` meters under
+    // them
+    pub fn set_heatmap(mut self, cell: f32) -> Self {
+        self.heatmap_cell = Some(cell);
+        self
+    }
+
+    // Localizes with range scans, e.g. of a `Lidar`, in place of landmarks
+    pub fn set_likelihood_field(mut self, likelihood_field: LikelihoodField) -> Self {
+        self.likelihood_field = Some(likelihood_field);
+        self
+    }
+
+    // The likelihoods of full scans are far too small for f32, so the
+    // weights are scaled by that of the most likely particle. A scan no
+    // particle can explain leaves the weights as they are.
+    fn scan_update(&mut self, scan: &[Observation]) {
+        let field = match self.likelihood_field.as_ref() {
+            Some(f) => f,
+            None => return,
+        };
+        let log_likelihoods = self
+            .particles
+            .iter()
+            .map(|p| field.scan_log_likelihood(p.pose, scan))
+            .collect::<Vec<f64>>();
+        let max = log_likelihoods
+            .iter()
+            .fold(f64::NEG_INFINITY, |m, &l| m.max(l));
+        if !max.is_finite() {
+            return;
+        }
+        for (p, l) in self.particles.iter_mut().zip(log_likelihoods) {
+            p.weight *= (l - max).exp() as f32;
+        }
+    }
+
+    fn normalize(&mut self) {
+        let num = self.particles.len() as f32;
+        let total: f32 = self.particles.iter().map(|p| p.weight).sum();
+        // Every particle is inconsistent with the observation; forget the weights
+        if total <= 0.0 || !total.is_finite() {
+            self.particles.iter_mut().for_each(|p| p.weight = 1.0 / num);
+            return;
+        }
+        self.particles.iter_mut().for_each(|p| p.weight /= total);
+    }
+
+    // Systematic resampling. Weights are expected to be normalized.
+    pub fn resampling(&mut self) {
+        let weights = self
+            .particles
+            .iter()
+            .map(|p| p.weight)
+            .collect::<Vec<f32>>();
+        let step = 1.0 / weights.len() as f32;
+        self.particles = systematic_sampling(&weights, &mut self.rng)
+            .into_iter()
+            .map(|i| Particle::new(self.particles[i].pose, step))
+            .collect();
+    }
+
+    // Pose of the particle with the largest weight
+    pub fn ml_pose(&self) -> (f32, f32, f32) {
+        self.particles
+            .iter()
+            .fold(None, |ml: Option<&Particle>, p| match ml {
+                Some(m) if m.weight >= p.weight => Some(m),
+                _ => Some(p),
+            })
+            .map(|p| p.pose)
+            .unwrap_or(self.pose)
+    }
+
+    // Weighted mean of the particles. The orientation is averaged on the circle.
+    pub fn mean_pose(&self) -> (f32, f32, f32) {
+        let total: f32 = self.particles.iter().map(|p| p.weight).sum();
+        let (x, y, s, c) = self
+            .particles
+            .iter()
+            .fold((0.0, 0.0, 0.0, 0.0), |(x, y, s, c), p| {
+                (
+                    x + p.pose.0 * p.weight,
+                    y + p.pose.1 * p.weight,
+                    s + p.pose.2.sin() * p.weight,
+                    c + p.pose.2.cos() * p.weight,
+                )
+            });
+        (x / total, y / total, s.atan2(c))
+    }
+
+    // Weighted covariance of the particles around their mean
+    pub fn particle_cov(&self) -> Array2<f64> {
+        let mean = self.mean_pose();
+        let total: f64 = self.particles.iter().map(|p| p.weight as f64).sum();
+        let mut cov = Array2::<f64>::zeros((3, 3));
+        for p in self.particles.iter() {
+            let d = Array1::from(vec![
+                (p.pose.0 - mean.0) as f64,
+                (p.pose.1 - mean.1) as f64,
+                normalize_angle(p.pose.2 - mean.2) as f64,
+            ]);
+            let d = d.into_shape((3, 1)).unwrap();
+            cov = cov + d.dot(&d.t()) * (p.weight as f64 / total);
+        }
+        cov
+    }
+
+    // Sum of the particle weights in the cells of `cell` meters covering
+    // [-xlim, xlim) x [-ylim, ylim). Cell [i, j] is the i-th from the left and
+    // the j-th from the bottom; particles outside are left out.
+    pub fn histogram(&self, cell: f32, xlim: i32, ylim: i32) -> Array2<f64> {
+        let nx = (2.0 * xlim as f32 / cell).ceil() as usize;
+        let ny = (2.0 * ylim as f32 / cell).ceil() as usize;
+        let mut histogram = Array2::<f64>::zeros((nx, ny));
+        for p in self.particles.iter() {
+            let i = ((p.pose.0 + xlim as f32) / cell).floor();
+            let j = ((p.pose.1 + ylim as f32) / cell).floor();
+            if i >= 0.0 && j >= 0.0 && (i as usize) < nx && (j as usize) < ny {
+                histogram[[i as usize, j as usize]] += p.weight as f64;
+            }
+        }
+        histogram
+    }
+
+    fn draw_heatmap<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        cell: f32,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        let histogram = self.histogram(cell, xlim, ylim);
+        let max = histogram.fold(0.0, |m: f64, &w| m.max(w));
+        if max <= 0.0 {
+            return;
+        }
+        for ((i, j), &w) in histogram.indexed_iter() {
+            if w <= 0.0 {
+                continue;
+            }
+            let x = -xlim as f32 + i as f32 * cell;
+            let y = -ylim as f32 + j as f32 * cell;
+            let upper_left = translate_coord(drawing_area, x, y + cell, xlim, ylim);
+            let lower_right = translate_coord(drawing_area, x + cell, y, xlim, ylim);
+            coord_spec
+                .draw(&Rectangle::new(
+                    [upper_left, lower_right],
+                    RED.mix(0.6 * w / max).filled(),
+                ))
+                .unwrap();
+        }
+    }
+}
+
+impl Estimator for Mcl {
+    fn motion_update(&mut self, nu: f32, omega: f32, time: f32) {
+        let (cov, rng) = (&self.motion_noise_cov, &mut self.rng);
+        self.particles.iter_mut().for_each(|p| {
+            p.motion_update(nu, omega, time, cov, rng);
+        });
+        self.weighted.clear();
+    }
+
+    fn observation_update(&mut self, observation: &[Observation]) {
+        if self.likelihood_field.is_some() {
+            self.scan_update(observation);
+        } else {
+            let map = &self.map;
+            let (distance_dev_rate, direction_dev) = (self.distance_dev_rate, self.direction_dev);
+            self.particles.iter_mut().for_each(|p| {
+                p.observation_update(observation, map, distance_dev_rate, direction_dev);
+            });
+        }
+        self.normalize();
+        self.pose = self.ml_pose();
+        self.weighted = self.particles.clone();
+        self.resampling();
+    }
+
+    fn pose(&self) -> (f32, f32, f32) {
+        self.pose
+    }
+
+    fn pose_cov(&self) -> Option<Array2<f64>> {
+        Some(self.particle_cov())
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.rng = rng;
+    }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        if let Some(cell) = self.heatmap_cell {
+            self.draw_heatmap(cell, drawing_area, xlim, ylim);
+        }
+
+        // Particles of the average weight keep the usual length
+        let coord_spec = drawing_area.strip_coord_spec();
+        let particles = if self.weighted.is_empty() {
+            &self.particles
+        } else {
+            &self.weighted
+        };
+        let num = particles.len() as f32;
+        particles.iter().for_each(|p| {
+            let (x, y, t) = p.pose;
+            let ratio = p.weight * num;
+            let length = (20.0 * ratio).clamp(3.0, 60.0);
+            let from = translate_coord(drawing_area, x, y, xlim, ylim);
+            let to = (
+                from.0 + (length * t.cos()) as i32,
+                from.1 + (length * -t.sin()) as i32,
+            );
+
+            let style = ShapeStyle::from(&BLUE.mix(ratio.clamp(0.1, 1.0) as f64));
+            coord_spec.draw(&Quiver::new(from, to, style)).unwrap();
+        });
+
+        let (x, y, t) = self.mean_pose();
+        let cov = self.particle_cov();
+        let ellipse = ErrorEllipse::new((x, y), &cov, 0.99, MAGENTA)
+            .map(|(x, y)| translate_coord(drawing_area, x, y, xlim, ylim));
+        coord_spec.draw(&ellipse).unwrap();
+        let from = translate_coord(drawing_area, x, y, xlim, ylim);
+        let to = (
+            from.0 + (20.0 * t.cos()) as i32,
+            from.1 + (20.0 * -t.sin()) as i32,
+        );
+        coord_spec
+            .draw(&Quiver::new(
+                from,
+                to,
+                ShapeStyle::from(&MAGENTA).stroke_width(2),
+            ))
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_observation_likelihood() {
+        let best = observation_likelihood((2.0, 0.1), (2.0, 0.1), 0.14, 0.05);
+        let worse = observation_likelihood((2.3, 0.1), (2.0, 0.1), 0.14, 0.05);
+        assert!(best > worse);
+
+        // Directions close to +-PI are compared on the circle
+        let wrapped = observation_likelihood((2.0, PI - 0.01), (2.0, -PI + 0.01), 0.14, 0.05);
+        assert!(wrapped > worse);
+
+        // A particle on the landmark expects a zero distance
+        assert!(observation_likelihood((0.0, 0.1), (0.0, 0.1), 0.14, 0.05).is_finite());
+        assert!(observation_likelihood((0.5, 0.1), (0.0, 0.1), 0.14, 0.05).is_finite());
+    }
+
+    #[test]
+    fn test_particle_observation_update() {
+        let map = landmarks();
+        let pose = (0.0, 0.0, 0.0);
+        let obs = vec![Observation::new(IdealCamera::obs_fn(pose, (3.0, 3.0)), 2)];
+
+        let mut right = Particle::new(pose, 1.0);
+        let mut wrong = Particle::new((1.0, -1.0, 0.5), 1.0);
+        right.observation_update(&obs, &map, 0.14, 0.05);
+        wrong.observation_update(&obs, &map, 0.14, 0.05);
+        assert!(right.weight > wrong.weight);
+    }
+
+    #[test]
+    fn test_resampling() {
+        let mut mcl = Mcl::new(landmarks(), (0.0, 0.0, 0.0), 10, motion_noise_cov());
+        for (i, p) in mcl.particles.iter_mut().enumerate() {
+            p.pose = (i as f32, 0.0, 0.0);
+            p.weight = if i == 3 { 1.0 } else { 0.0 };
+        }
+        mcl.resampling();
+
+        assert_eq!(mcl.particles.len(), 10);
+        mcl.particles.iter().for_each(|p| {
+            assert_eq!(p.pose, (3.0, 0.0, 0.0));
+            assert_eq!(p.weight, 0.1);
+        });
+    }
+
+    #[test]
+    fn test_pose_estimate() {
+        let mut mcl = Mcl::new(landmarks(), (0.0, 0.0, 0.0), 2, motion_noise_cov());
+        mcl.particles[0] = Particle::new((1.0, 1.0, PI - 0.1), 0.75);
+        mcl.particles[1] = Particle::new((-1.0, 1.0, -PI + 0.1), 0.25);
+
+        assert_eq!(mcl.ml_pose(), (1.0, 1.0, PI - 0.1));
+        let (x, y, t) = mcl.mean_pose();
+        assert!((x - 0.5).abs() < 1e-5);
+        assert!((y - 1.0).abs() < 1e-5);
+        assert!(normalize_angle(t - PI).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_belief_heatmap() {
+        let mut mcl =
+            Mcl::new(landmarks(), (0.0, 0.0, 0.0), 3, motion_noise_cov()).set_heatmap(1.0);
+        mcl.particles[0] = Particle::new((-4.5, -4.5, 0.0), 0.5);
+        mcl.particles[1] = Particle::new((0.2, 0.7, 0.0), 0.25);
+        mcl.particles[2] = Particle::new((7.0, 0.0, 0.0), 0.25);
+
+        let histogram = mcl.histogram(1.0, 5, 5);
+        assert_eq!(histogram.dim(), (10, 10));
+        assert_eq!(histogram[[0, 0]], 0.5);
+        assert_eq!(histogram[[5, 5]], 0.25);
+        assert_eq!(histogram.sum(), 0.75);
+
+        let mut buffer = vec![255u8; 100 * 100 * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut buffer, (100, 100)).into_drawing_area();
+            let area = root.apply_coord_spec(Cartesian2d::<
+                plotters::coord::types::RangedCoordi32,
+                plotters::coord::types::RangedCoordi32,
+            >::new(-5..5, -5..5, (0..100, 0..100)));
+            mcl.draw(&area, 5, 5);
+            root.present().unwrap();
+        }
+        // the fullest cell is the lower left one
+        let pixel = |x: usize, y: usize| &buffer[(y * 100 + x) * 3..(y * 100 + x) * 3 + 3];
+        assert_eq!(pixel(2, 97)[0], 255);
+        assert!(pixel(2, 97)[1] < 150);
+        assert_eq!(pixel(30, 70), &[255, 255, 255]);
+    }
+
+    #[test]
+    fn test_drawn_weights_follow_observation() {
+        let map = landmarks();
+        let mut mcl = Mcl::new(map.clone(), (0.0, 0.0, 0.0), 100, motion_noise_cov())
+            .set_rng(SimRng::seed_from_u64(3));
+        for _ in 0..10 {
+            mcl.motion_update(0.2, 0.1, 1.0);
+        }
+        assert!(mcl.weighted.is_empty());
+
+        let obs = vec![Observation::new(
+            IdealCamera::obs_fn(mcl.particles[0].pose, (3.0, 3.0)),
+            2,
+        )];
+        mcl.observation_update(&obs);
+
+        // resampling leaves the particles even, the drawn ones keep the weights
+        assert!(mcl.particles.iter().all(|p| p.weight == 0.01));
+        let weights = mcl.weighted.iter().map(|p| p.weight).collect::<Vec<f32>>();
+        assert_eq!(weights.len(), 100);
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        let max = weights.iter().fold(0.0f32, |m, &w| m.max(w));
+        let min = weights.iter().fold(1.0f32, |m, &w| m.min(w));
+        assert!(max > 0.05 && min < 0.005);
+    }
+
+    #[test]
+    fn test_unexplained_scan_keeps_weights() {
+        let mut map = Map::new();
+        map.append_wall((2.0, -2.0), (2.0, 2.0));
+        // without random readings, beams far from everything are impossible
+        let field = LikelihoodField::from_map(&map, 3, 3, 0.05, 5.0).set_noise(0.01, 1.0, 0.0);
+        let scan = [Observation::new((1.0, 0.0), 0)];
+        assert_eq!(
+            field.scan_log_likelihood((-2.0, 0.0, 0.0), &scan),
+            f64::NEG_INFINITY
+        );
+
+        let mut mcl =
+            Mcl::new(map, (-2.0, 0.0, 0.0), 2, motion_noise_cov()).set_likelihood_field(field);
+        mcl.particles[0].weight = 0.75;
+        mcl.particles[1].weight = 0.25;
+        mcl.scan_update(&scan);
+        assert_eq!(mcl.particles[0].weight, 0.75);
+        assert_eq!(mcl.particles[1].weight, 0.25);
+    }
+
+    #[test]
+    fn test_mcl_localizes() {
+        let map = landmarks();
+        let true_pose = (0.5, -0.5, PI / 4.0);
+        let mut rng = SimRng::seed_from_u64(1);
+        let mut mcl =
+            Mcl::new(map.clone(), (0.0, 0.0, 0.0), 200, motion_noise_cov()).set_rng(rng.clone());
+        let spread = Uniform::from(-1.0..1.0);
+        mcl.particles.iter_mut().for_each(|p| {
+            p.pose = (
+                spread.sample(&mut rng),
+                spread.sample(&mut rng),
+                PI / 4.0 + 0.2 * spread.sample(&mut rng),
+            );
+        });
+
+        let obs = map
+            .landmarks
+            .iter()
+            .map(|l| Observation::new(IdealCamera::obs_fn(true_pose, l.position), l.id))
+            .collect::<Vec<Observation>>();
+        mcl.observation_update(&obs);
+
+        let (x, y, _) = mcl.pose;
+        assert!((x - true_pose.0).abs() < 0.5);
+        assert!((y - true_pose.1).abs() < 0.5);
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use ndarray::{arr1, Array2};
+use ndarray_linalg::Inverse;
+
+use crate::base::*;
+
+// Error of `estimate` against `truth` in the world frame. The orientation
+// error is wrapped into [-PI, PI).
+pub fn pose_error(truth: (f32, f32, f32), estimate: (f32, f32, f32)) -> (f32, f32, f32) {
+    (
+        estimate.0 - truth.0,
+        estimate.1 - truth.1,
+        normalize_angle(estimate.2 - truth.2),
+    )
+}
+
+// Motion from `from` to `to` in the frame of `from`
+pub fn relative_motion(from: (f32, f32, f32), to: (f32, f32, f32)) -> (f32, f32, f32) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (s, c) = from.2.sin_cos();
+    (
+        c * dx + s * dy,
+        -s * dx + c * dy,
+        normalize_angle(to.2 - from.2),
+    )
+}
+
+// Normalized estimation error squared, e^T P^-1 e. None if `cov` is singular.
+pub fn nees(error: (f32, f32, f32), cov: &Array2<f64>) -> Option<f64> {
+    let e = arr1(&[error.0 as f64, error.1 as f64, error.2 as f64]);
+    let inv = cov.inv().ok()?;
+    Some(e.dot(&inv.dot(&e)))
+}
+
+// Normalized innovation squared, v^T S^-1 v. None if `S` is singular.
+pub fn nis(innovation: &Innovation) -> Option<f64> {
+    let v = &innovation.value;
+    let inv = innovation.cov.inv().ok()?;
+    Some(v.dot(&inv.dot(v)))
+}
+
+// Errors at one step. The relative pose error compares the motion over the
+// last `delta` steps and is None for the first ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepMetrics {
+    pub step: usize,
+    pub ate: f32,
+    pub heading_error: f32,
+    pub rpe_translation: Option<f32>,
+    pub rpe_rotation: Option<f32>,
+    pub nees: Option<f64>,
+    // mean over the innovations of the step
+    pub nis: Option<f64>,
+}
+
+// Root mean square of the errors over a run. NEES and NIS are means, which
+// should stay near 3 and 2 for a consistent filter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsSummary {
+    pub steps: usize,
+    pub ate_rmse: f32,
+    pub ate_max: f32,
+    pub heading_rmse: f32,
+    pub rpe_translation_rmse: Option<f32>,
+    pub rpe_rotation_rmse: Option<f32>,
+    pub mean_nees: Option<f64>,
+    pub mean_nis: Option<f64>,
+}
+
+impl fmt::Display for MetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.4}", v));
+        write!(
+            f,
+            "steps {} ATE {:.4} (max {:.4}) heading {:.4} RPE {} / {} NEES {} NIS {}",
+            self.steps,
+            self.ate_rmse,
+            self.ate_max,
+            self.heading_rmse,
+            opt(self.rpe_translation_rmse.map(f64::from)),
+            opt(self.rpe_rotation_rmse.map(f64::from)),
+            opt(self.mean_nees),
+            opt(self.mean_nis),
+        )
+    }
+}
+
+// Accuracy of an estimator against the true trajectory. `estimates` starts
+// with the initial estimate, followed by one after every step, as collected
+// around `World::step` or returned by `Log::replay`. A robot observes before it
+// moves, so the estimate after step i is the belief about the pose the step
+// started from: `estimates[i + 1]` is scored against `truth[i]`. Both are in
+// the world frame, so no alignment is done before the absolute trajectory
+// error.
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    pub steps: Vec<StepMetrics>,
+    pub summary: MetricsSummary,
+}
+
+impl Evaluation {
+    pub fn new(truth: &[(f32, f32, f32)], estimates: &[Estimate], rpe_delta: usize) -> Self {
+        let estimates = estimates.get(1..).unwrap_or_default();
+        let n = truth.len().min(estimates.len());
+        let steps = (0..n)
+            .map(|i| {
+                let (ex, ey, et) = pose_error(truth[i], estimates[i].pose);
+                let rpe = if i >= rpe_delta && rpe_delta > 0 {
+                    let j = i - rpe_delta;
+                    let t = relative_motion(truth[j], truth[i]);
+                    let e = relative_motion(estimates[j].pose, estimates[i].pose);
+                    let (dx, dy, dt) = pose_error(t, e);
+                    Some(((dx * dx + dy * dy).sqrt(), dt.abs()))
+                } else {
+                    None
+                };
+                let step_nis = estimates[i]
+                    .innovations
+                    .iter()
+                    .filter_map(nis)
+                    .collect::<Vec<f64>>();
+                StepMetrics {
+                    step: i,
+                    ate: (ex * ex + ey * ey).sqrt(),
+                    heading_error: et,
+                    rpe_translation: rpe.map(|r| r.0),
+                    rpe_rotation: rpe.map(|r| r.1),
+                    nees: estimates[i]
+                        .cov
+                        .as_ref()
+                        .and_then(|c| nees((ex, ey, et), c)),
+                    nis: mean(&step_nis),
+                }
+            })
+            .collect::<Vec<StepMetrics>>();
+
+        let all_nis = estimates[..n]
+            .iter()
+            .flat_map(|e| e.innovations.iter().filter_map(nis))
+            .collect::<Vec<f64>>();
+        let summary = MetricsSummary {
+            steps: n,
+            ate_rmse: rms(steps.iter().map(|s| s.ate)).unwrap_or(0.0),
+            ate_max: steps.iter().map(|s| s.ate).fold(0.0, f32::max),
+            heading_rmse: rms(steps.iter().map(|s| s.heading_error)).unwrap_or(0.0),
+            rpe_translation_rmse: rms(steps.iter().filter_map(|s| s.rpe_translation)),
+            rpe_rotation_rmse: rms(steps.iter().filter_map(|s| s.rpe_rotation)),
+            mean_nees: mean(&steps.iter().filter_map(|s| s.nees).collect::<Vec<f64>>()),
+            mean_nis: mean(&all_nis),
+        };
+        Evaluation { steps, summary }
+    }
+
+    // One line per step; the columns without a value are left empty
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        writeln!(
+            f,
+            "step,ate,heading_error,rpe_translation,rpe_rotation,nees,nis"
+        )?;
+        let opt = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+        for s in self.steps.iter() {
+            writeln!(
+                f,
+                "{},{},{},{},{},{},{}",
+                s.step,
+                s.ate,
+                s.heading_error,
+                opt(s.rpe_translation.map(f64::from)),
+                opt(s.rpe_rotation.map(f64::from)),
+                opt(s.nees),
+                opt(s.nis),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn rms<I: Iterator<Item = f32>>(values: I) -> Option<f32> {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v * v, n + 1));
+    if n == 0 {
+        return None;
+    }
+    Some((sum / n as f32).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kf::*;
+    use crate::robot::*;
+    use crate::test_util::*;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_errors_wrap_around() {
+        let e = pose_error((0.0, 0.0, PI - 0.1), (1.0, 0.0, -PI + 0.1));
+        assert_eq!(e.0, 1.0);
+        assert!((e.2 - 0.2).abs() < 1e-5);
+
+        // The same motion seen from two frames rotated by almost a turn
+        let a = relative_motion((0.0, 0.0, 3.1), (-1.0, 0.05, -3.1));
+        let b = relative_motion((2.0, 1.0, -3.1), (1.0, 0.95, 3.1));
+        assert!((a.0 - 1.0).abs() < 1e-2 && (b.0 - 1.0).abs() < 1e-2);
+        assert!((a.2 - (2.0 * PI - 6.2)).abs() < 1e-5);
+        assert!((b.2 + (2.0 * PI - 6.2)).abs() < 1e-5);
+
+        let cov = Array2::from_diag(&arr1(&[0.25, 0.25, 0.01]));
+        assert!((nees((0.5, 0.0, 0.1), &cov).unwrap() - 2.0).abs() < 1e-6);
+        let singular = Array2::<f64>::zeros((3, 3));
+        assert_eq!(nees((0.5, 0.0, 0.1), &singular), None);
+    }
+
+    #[test]
+    fn test_evaluation_summary() {
+        let truth = vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, 0.0, 0.0)];
+        let estimates = vec![
+            Estimate::new((5.0, 5.0, 0.0)),
+            Estimate::new((0.0, 0.0, 0.0)),
+            Estimate::new((1.0, 1.0, 0.0)),
+            Estimate::new((2.0, 1.0, 0.0)),
+        ];
+        let evaluation = Evaluation::new(&truth, &estimates, 1);
+        let s = &evaluation.summary;
+        assert_eq!(s.steps, 3);
+        assert!((s.ate_rmse - (2.0f32 / 3.0).sqrt()).abs() < 1e-6);
+        assert_eq!(s.ate_max, 1.0);
+        assert_eq!(evaluation.steps[0].rpe_translation, None);
+        assert_eq!(evaluation.steps[2].rpe_translation, Some(0.0));
+        assert!((s.rpe_translation_rmse.unwrap() - 0.5f32.sqrt()).abs() < 1e-6);
+        assert_eq!(s.mean_nees, None);
+        assert_eq!(s.mean_nis, None);
+    }
+
+    #[test]
+    fn test_kalman_filter_is_consistent() {
+        let map = landmarks();
+        let cov = motion_noise_cov();
+        let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1).set_seed(4);
+        let kf = KalmanFilter::new(map.clone(), (0.0, 0.0, 0.0), cov)
+            .set_observation_noise(0.1, PI / 90.0);
+        let agent = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, kf);
+        let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0)).set_bias(0.0, 0.0);
+        let robot = Robot::new((0.0, 0.0, 0.0), &BLACK, agent, camera).set_bias((0.0, 0.0));
+        world.push(Box::new(robot));
+
+        let mut estimates = vec![world.objects()[0].estimate().unwrap()];
+        for _ in 0..world.max_iteration() {
+            world.step();
+            estimates.push(world.objects()[0].estimate().unwrap());
+        }
+        let evaluation = Evaluation::new(&world.objects()[0].poses(), &estimates, 10);
+        assert!(evaluation.summary.ate_rmse < 0.3);
+        assert!(evaluation.summary.mean_nis.unwrap() < 2.0 * 2.0);
+        // NEES of a consistent filter follows the chi-square distribution with
+        // 3 degrees of freedom, whose 95% interval is [0.216, 9.348]
+        let nees = evaluation
+            .steps
+            .iter()
+            .filter_map(|s| s.nees)
+            .collect::<Vec<f64>>();
+        let inside = nees.iter().filter(|&&e| e > 0.216 && e < 9.348).count();
+        assert_eq!(nees.len(), evaluation.summary.steps);
+        assert!(inside as f64 >= 0.9 * nees.len() as f64);
+    }
+}
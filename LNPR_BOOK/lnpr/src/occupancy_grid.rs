@@ -0,0 +1,240 @@
+use ndarray::Array2;
+use plotters::prelude::*;
+
+use crate::base::*;
+use crate::log::Log;
+
+// Grid of log odds of being occupied over [-xlim, xlim) x [-ylim, ylim),
+// built from range scans taken at known poses. Cell [i, j] is the i-th from
+// the left and the j-th from the bottom.
+#[derive(Clone, Debug)]
+pub struct OccupancyGrid {
+    pub log_odds: Array2<f32>,
+    pub cell: f32,
+    pub xlim: i32,
+    pub ylim: i32,
+    // readings at or beyond it hit nothing
+    pub max_range: f32,
+    // added to the cell a beam ends in and to the cells it passes through
+    pub occupied_log_odds: f32,
+    pub free_log_odds: f32,
+    // the log odds stay within +-this, so the map can still change
+    pub clamp: f32,
+}
+
+impl OccupancyGrid {
+    pub fn new(xlim: i32, ylim: i32, cell: f32, max_range: f32) -> Self {
+        let nx = (2.0 * xlim as f32 / cell).ceil() as usize;
+        let ny = (2.0 * ylim as f32 / cell).ceil() as usize;
+        OccupancyGrid {
+            log_odds: Array2::zeros((nx, ny)),
+            cell,
+            xlim,
+            ylim,
+            max_range,
+            occupied_log_odds: 0.85,
+            free_log_odds: -0.4,
+            clamp: 5.0,
+        }
+    }
+
+    pub fn set_log_odds(mut self, occupied: f32, free: f32, clamp: f32) -> Self {
+        self.occupied_log_odds = occupied;
+        self.free_log_odds = free;
+        self.clamp = clamp;
+        self
+    }
+
+    pub fn index(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let i = ((x + self.xlim as f32) / self.cell).floor();
+        let j = ((y + self.ylim as f32) / self.cell).floor();
+        let (nx, ny) = self.log_odds.dim();
+        if i >= 0.0 && j >= 0.0 && (i as usize) < nx && (j as usize) < ny {
+            Some((i as usize, j as usize))
+        } else {
+            None
+        }
+    }
+
+    // Center of the cell [i, j]
+    pub fn position(&self, i: usize, j: usize) -> (f32, f32) {
+        (
+            -self.xlim as f32 + (i as f32 + 0.5) * self.cell,
+            -self.ylim as f32 + (j as f32 + 0.5) * self.cell,
+        )
+    }
+
+    pub fn probability(&self, i: usize, j: usize) -> f32 {
+        1.0 - 1.0 / (1.0 + self.log_odds[[i, j]].exp())
+    }
+
+    pub fn is_occupied(&self, i: usize, j: usize) -> bool {
+        self.log_odds[[i, j]] > 0.0
+    }
+
+    // Cells the segment from `from` to `to` passes through, in order, found
+    // by stepping a quarter of a cell at a time
+    fn traverse(&self, from: (f32, f32), to: (f32, f32)) -> Vec<(usize, usize)> {
+        let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        let steps = (length / (0.25 * self.cell)).ceil() as usize;
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+        for k in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                k as f32 / steps as f32
+            };
+            let p = (from.0 + t * (to.0 - from.0), from.1 + t * (to.1 - from.1));
+            if let Some(c) = self.index(p.0, p.1) {
+                if cells.last() != Some(&c) {
+                    cells.push(c);
+                }
+            }
+        }
+        cells
+    }
+
+    fn add(&mut self, c: (usize, usize), l: f32) {
+        let v = &mut self.log_odds[[c.0, c.1]];
+        *v = (*v + l).clamp(-self.clamp, self.clamp);
+    }
+
+    // Inverse sensor model: the cells before the end of each beam are free and
+    // the cell it ends in is occupied, unless the beam hit nothing
+    pub fn update(&mut self, pose: (f32, f32, f32), scan: &[Observation]) {
+        let (x, y, theta) = pose;
+        for obs in scan.iter() {
+            let (distance, direction) = obs.pos;
+            let hit = distance < self.max_range;
+            let reach = distance.min(self.max_range);
+            let end = (
+                x + reach * (theta + direction).cos(),
+                y + reach * (theta + direction).sin(),
+            );
+            let mut cells = self.traverse((x, y), end);
+            // a beam ending outside the grid only clears the cells inside it
+            let end_cell = if hit { self.index(end.0, end.1) } else { None };
+            let last = match end_cell {
+                Some(c) if cells.last() == Some(&c) => cells.pop(),
+                _ => None,
+            };
+            for c in cells {
+                self.add(c, self.free_log_odds);
+            }
+            if let Some(c) = last {
+                self.add(c, self.occupied_log_odds);
+            }
+        }
+    }
+
+    // Every step of a log, at the true poses it recorded
+    pub fn update_from_log(&mut self, log: &Log) {
+        for step in log.steps.iter() {
+            self.update(step.pose, &step.observations);
+        }
+    }
+
+    // Gray levels from white for free to black for occupied
+    pub fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        let coord_spec = drawing_area.strip_coord_spec();
+        for ((i, j), _) in self.log_odds.indexed_iter() {
+            let level = (255.0 * (1.0 - self.probability(i, j))).round() as u8;
+            let (x, y) = self.position(i, j);
+            let half = 0.5 * self.cell;
+            let upper_left = translate_coord(drawing_area, x - half, y + half, xlim, ylim);
+            let lower_right = translate_coord(drawing_area, x + half, y - half, xlim, ylim);
+            coord_spec
+                .draw(&Rectangle::new(
+                    [upper_left, lower_right],
+                    RGBColor(level, level, level).filled(),
+                ))
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lidar::*;
+    use std::f32::consts::PI;
+
+    fn room() -> Map {
+        let mut map = Map::new();
+        map.append_wall((2.0, -2.0), (2.0, 2.0));
+        map.append_wall((-2.0, 2.0), (2.0, 2.0));
+        map
+    }
+
+    #[test]
+    fn test_update_from_scan() {
+        let mut grid = OccupancyGrid::new(3, 3, 0.1, 4.0);
+        let scan = [
+            Observation::new((2.0, 0.0), 0),
+            Observation::new((4.0, PI), 1),
+        ];
+        grid.update((0.0, 0.05, 0.0), &scan);
+
+        let (wall, before) = (
+            grid.index(2.05, 0.05).unwrap(),
+            grid.index(1.0, 0.05).unwrap(),
+        );
+        assert!(grid.is_occupied(wall.0, wall.1));
+        assert!(grid.probability(before.0, before.1) < 0.5);
+        // a max range beam clears its way without marking anything
+        let behind = grid.index(-2.95, 0.05).unwrap();
+        assert!(grid.probability(behind.0, behind.1) < 0.5);
+        let unknown = grid.index(0.0, 1.0).unwrap();
+        assert_eq!(grid.probability(unknown.0, unknown.1), 0.5);
+
+        for _ in 0..100 {
+            grid.update((0.0, 0.05, 0.0), &scan);
+        }
+        assert_eq!(grid.log_odds[[wall.0, wall.1]], 5.0);
+    }
+
+    #[test]
+    fn test_hit_outside_grid_marks_nothing_occupied() {
+        let mut grid = OccupancyGrid::new(3, 3, 0.1, 4.0);
+        grid.update((0.0, 0.05, 0.0), &[Observation::new((3.5, 0.0), 0)]);
+
+        let edge = grid.index(2.95, 0.05).unwrap();
+        assert!(grid.probability(edge.0, edge.1) < 0.5);
+        assert!(grid.log_odds.iter().all(|&l| l <= 0.0));
+    }
+
+    #[test]
+    fn test_map_world_with_lidar() {
+        let lidar = Lidar::new(room(), (0.1, 4.0), (-PI, PI), 180);
+        let agent = Agent {
+            nu: 0.2,
+            omega: 0.1,
+        };
+        let robot = IdealRobot::new((0.0, 0.0, 0.0), &BLACK, agent, lidar);
+        let mut world =
+            World::new(room(), 3, 3, 5.0, 0.5).set_grid(OccupancyGrid::new(3, 3, 0.1, 4.0), 0);
+        world.push(Box::new(robot));
+        world.run();
+
+        let grid = world.grid.as_ref().unwrap();
+        let occupied = grid.log_odds.iter().filter(|&&l| l > 0.0).count();
+        assert!(occupied > 40);
+        let wall = grid.index(2.0, 0.5).unwrap();
+        assert!(grid.is_occupied(wall.0, wall.1) || grid.is_occupied(wall.0 - 1, wall.1));
+        let inside = grid.index(0.5, 0.5).unwrap();
+        assert!(grid.probability(inside.0, inside.1) < 0.1);
+
+        let mut buffer = vec![0; 300 * 300 * 3];
+        let root = BitMapBackend::with_buffer(&mut buffer, (300, 300)).into_drawing_area();
+        world.draw_frame(0, &root);
+        drop(root);
+        // free cells are drawn white and the wall black
+        let pixel = |x: usize, y: usize| buffer[(y * 300 + x) * 3];
+        assert!((0..300 * 300).any(|p| pixel(p % 300, p / 300) == 0));
+    }
+}
@@ -1,6 +1,15 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+
+use ndarray::Array2;
+use plotters::coord::Shift;
+use plotters::drawing::{DrawingArea, IntoDrawingArea};
 use plotters::element::{Drawable, PointCollection};
-use plotters::style::ShapeStyle;
-use plotters_backend::{BackendColor, BackendCoord, DrawingBackend, DrawingErrorKind};
+use plotters::style::{Color, ShapeStyle, BLACK};
+use plotters_backend::{
+    BackendColor, BackendCoord, BackendStyle, DrawingBackend, DrawingErrorKind,
+};
 
 // Quiver elemnt
 pub struct Quiver<Coord> {
@@ -81,6 +90,459 @@ impl<Coord, DB: DrawingBackend> Drawable<DB> for Quiver<Coord> {
     }
 }
 
+// Number of segments of the arcs of the elements below
+const ARC_SEGMENTS: usize = 36;
+
+fn arc(center: (f32, f32), radius: f32, from: f32, to: f32) -> impl Iterator<Item = (f32, f32)> {
+    (0..=ARC_SEGMENTS).map(move |i| {
+        let t = from + (to - from) * i as f32 / ARC_SEGMENTS as f32;
+        (center.0 + radius * t.cos(), center.1 + radius * t.sin())
+    })
+}
+
+// Draws the closed outline `points`, filled if the style says so
+fn draw_outline<DB: DrawingBackend>(
+    points: Vec<BackendCoord>,
+    style: &ShapeStyle,
+    backend: &mut DB,
+) -> Result<(), DrawingErrorKind<DB::ErrorType>> {
+    if style.filled {
+        backend.fill_polygon(points, style)
+    } else {
+        backend.draw_path(points, style)
+    }
+}
+
+// Error ellipse of a 2D gaussian: the border of the region where the true
+// position lies with probability `confidence` (0 < confidence < 1).
+// The elements below are built in world coordinates; `map` converts their
+// points, e.g. into pixels with `translate_coord`.
+pub struct ErrorEllipse<Coord> {
+    points: Vec<Coord>,
+    style: ShapeStyle,
+}
+
+impl ErrorEllipse<(f32, f32)> {
+    // Only the x-y block of `cov` is used, so a pose covariance can be given
+    pub fn new<S: Into<ShapeStyle>>(
+        mean: (f32, f32),
+        cov: &Array2<f64>,
+        confidence: f64,
+        style: S,
+    ) -> Self {
+        let (a, b, c) = (cov[[0, 0]], cov[[0, 1]], cov[[1, 1]]);
+        let mid = (a + c) / 2.0;
+        let diff = ((a - c).powi(2) / 4.0 + b * b).sqrt();
+        let angle = 0.5 * (2.0 * b).atan2(a - c);
+        // The squared Mahalanobis distance follows a chi-squared distribution
+        // with 2 degrees of freedom
+        let k = (-2.0 * (1.0 - confidence).ln()).sqrt();
+        let width = k * (mid + diff).max(0.0).sqrt();
+        let height = k * (mid - diff).max(0.0).sqrt();
+
+        let (s, c) = angle.sin_cos();
+        let points = (0..=ARC_SEGMENTS)
+            .map(|i| {
+                let t = 2.0 * PI * i as f64 / ARC_SEGMENTS as f64;
+                let (u, v) = (width * t.cos(), height * t.sin());
+                (
+                    mean.0 + (c * u - s * v) as f32,
+                    mean.1 + (s * u + c * v) as f32,
+                )
+            })
+            .collect();
+        Self {
+            points,
+            style: style.into(),
+        }
+    }
+}
+
+impl<Coord> ErrorEllipse<Coord> {
+    pub fn map<T, F: FnMut(Coord) -> T>(self, f: F) -> ErrorEllipse<T> {
+        ErrorEllipse {
+            points: self.points.into_iter().map(f).collect(),
+            style: self.style,
+        }
+    }
+}
+
+impl<'a, Coord> PointCollection<'a, Coord> for &'a ErrorEllipse<Coord> {
+    type Point = &'a Coord;
+    type IntoIter = &'a [Coord];
+    fn point_iter(self) -> &'a [Coord] {
+        &self.points
+    }
+}
+
+impl<Coord, DB: DrawingBackend> Drawable<DB> for ErrorEllipse<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        points: I,
+        backend: &mut DB,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<DB::ErrorType>> {
+        draw_outline(points.collect(), &self.style, backend)
+    }
+}
+
+// Area seen by a sensor at `pose`, between the two distances of
+// `distance_range` and the two directions of `direction_range`
+pub struct FieldOfView<Coord> {
+    points: Vec<Coord>,
+    style: ShapeStyle,
+}
+
+impl FieldOfView<(f32, f32)> {
+    pub fn new<S: Into<ShapeStyle>>(
+        pose: (f32, f32, f32),
+        distance_range: (f32, f32),
+        direction_range: (f32, f32),
+        style: S,
+    ) -> Self {
+        let center = (pose.0, pose.1);
+        let (from, to) = (pose.2 + direction_range.0, pose.2 + direction_range.1);
+        let mut points: Vec<(f32, f32)> = arc(center, distance_range.1, from, to).collect();
+        points.extend(arc(center, distance_range.0, to, from));
+        points.push(points[0]);
+        Self {
+            points,
+            style: style.into(),
+        }
+    }
+}
+
+impl<Coord> FieldOfView<Coord> {
+    pub fn map<T, F: FnMut(Coord) -> T>(self, f: F) -> FieldOfView<T> {
+        FieldOfView {
+            points: self.points.into_iter().map(f).collect(),
+            style: self.style,
+        }
+    }
+}
+
+impl<'a, Coord> PointCollection<'a, Coord> for &'a FieldOfView<Coord> {
+    type Point = &'a Coord;
+    type IntoIter = &'a [Coord];
+    fn point_iter(self) -> &'a [Coord] {
+        &self.points
+    }
+}
+
+impl<Coord, DB: DrawingBackend> Drawable<DB> for FieldOfView<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        points: I,
+        backend: &mut DB,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<DB::ErrorType>> {
+        draw_outline(points.collect(), &self.style, backend)
+    }
+}
+
+// Round robot body of `radius` with a line from its center to the front
+pub struct RobotGlyph<Coord> {
+    // the outline, then the center and the front
+    points: Vec<Coord>,
+    style: ShapeStyle,
+}
+
+impl RobotGlyph<(f32, f32)> {
+    pub fn new<S: Into<ShapeStyle>>(pose: (f32, f32, f32), radius: f32, style: S) -> Self {
+        let center = (pose.0, pose.1);
+        let mut points: Vec<(f32, f32)> = arc(center, radius, 0.0, 2.0 * PI as f32).collect();
+        points.push(center);
+        points.push((
+            pose.0 + radius * pose.2.cos(),
+            pose.1 + radius * pose.2.sin(),
+        ));
+        Self {
+            points,
+            style: style.into(),
+        }
+    }
+}
+
+impl<Coord> RobotGlyph<Coord> {
+    pub fn map<T, F: FnMut(Coord) -> T>(self, f: F) -> RobotGlyph<T> {
+        RobotGlyph {
+            points: self.points.into_iter().map(f).collect(),
+            style: self.style,
+        }
+    }
+}
+
+impl<'a, Coord> PointCollection<'a, Coord> for &'a RobotGlyph<Coord> {
+    type Point = &'a Coord;
+    type IntoIter = &'a [Coord];
+    fn point_iter(self) -> &'a [Coord] {
+        &self.points
+    }
+}
+
+impl<Coord, DB: DrawingBackend> Drawable<DB> for RobotGlyph<Coord> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        points: I,
+        backend: &mut DB,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<DB::ErrorType>> {
+        let mut points: Vec<BackendCoord> = points.collect();
+        if points.len() < 2 {
+            return Ok(());
+        }
+        let front = points.pop().unwrap();
+        let center = points.pop().unwrap();
+        draw_outline(points, &self.style, backend)?;
+        // the front line stays visible on a filled body
+        let line = ShapeStyle {
+            color: if self.style.filled {
+                BLACK.to_rgba()
+            } else {
+                self.style.color
+            },
+            filled: false,
+            stroke_width: self.style.stroke_width,
+        };
+        backend.draw_line(center, front, &line)
+    }
+}
+
+// Error of whichever backend is behind a `DynBackend`
+#[derive(Debug)]
+pub struct DynBackendError(String);
+
+impl fmt::Display for DynBackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for DynBackendError {}
+
+type DynResult = Result<(), DrawingErrorKind<DynBackendError>>;
+
+fn erase<E: Error + Send + Sync>(e: DrawingErrorKind<E>) -> DrawingErrorKind<DynBackendError> {
+    match e {
+        DrawingErrorKind::DrawingError(e) => {
+            DrawingErrorKind::DrawingError(DynBackendError(e.to_string()))
+        }
+        DrawingErrorKind::FontError(e) => DrawingErrorKind::FontError(e),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct DynStyle {
+    color: BackendColor,
+    width: u32,
+}
+
+impl DynStyle {
+    fn of<S: BackendStyle>(style: &S) -> Self {
+        DynStyle {
+            color: style.color(),
+            width: style.stroke_width(),
+        }
+    }
+}
+
+impl BackendStyle for DynStyle {
+    fn color(&self) -> BackendColor {
+        self.color
+    }
+
+    fn stroke_width(&self) -> u32 {
+        self.width
+    }
+}
+
+// The shape drawing part of `DrawingBackend` without generic methods
+trait ErasedBackend {
+    fn size(&self) -> (u32, u32);
+    fn pixel(&mut self, point: BackendCoord, color: BackendColor) -> DynResult;
+    fn line(&mut self, from: BackendCoord, to: BackendCoord, style: DynStyle) -> DynResult;
+    fn rect(
+        &mut self,
+        ul: BackendCoord,
+        br: BackendCoord,
+        style: DynStyle,
+        fill: bool,
+    ) -> DynResult;
+    fn path(&mut self, path: Vec<BackendCoord>, style: DynStyle) -> DynResult;
+    fn circle(
+        &mut self,
+        center: BackendCoord,
+        radius: u32,
+        style: DynStyle,
+        fill: bool,
+    ) -> DynResult;
+    fn polygon(&mut self, vert: Vec<BackendCoord>, style: DynStyle) -> DynResult;
+}
+
+impl<DB: DrawingBackend> ErasedBackend for DB {
+    fn size(&self) -> (u32, u32) {
+        self.get_size()
+    }
+
+    fn pixel(&mut self, point: BackendCoord, color: BackendColor) -> DynResult {
+        self.draw_pixel(point, color).map_err(erase)
+    }
+
+    fn line(&mut self, from: BackendCoord, to: BackendCoord, style: DynStyle) -> DynResult {
+        self.draw_line(from, to, &style).map_err(erase)
+    }
+
+    fn rect(
+        &mut self,
+        ul: BackendCoord,
+        br: BackendCoord,
+        style: DynStyle,
+        fill: bool,
+    ) -> DynResult {
+        self.draw_rect(ul, br, &style, fill).map_err(erase)
+    }
+
+    fn path(&mut self, path: Vec<BackendCoord>, style: DynStyle) -> DynResult {
+        self.draw_path(path, &style).map_err(erase)
+    }
+
+    fn circle(
+        &mut self,
+        center: BackendCoord,
+        radius: u32,
+        style: DynStyle,
+        fill: bool,
+    ) -> DynResult {
+        self.draw_circle(center, radius, &style, fill)
+            .map_err(erase)
+    }
+
+    fn polygon(&mut self, vert: Vec<BackendCoord>, style: DynStyle) -> DynResult {
+        self.fill_polygon(vert, &style).map_err(erase)
+    }
+}
+
+// Any backend behind one type, for drawing code which cannot be generic such
+// as `Robotize::draw`. Shapes go to the backend underneath; text is drawn
+// pixel by pixel.
+pub struct DynBackend<'a> {
+    inner: &'a mut (dyn ErasedBackend + 'a),
+}
+
+impl<'a> DrawingBackend for DynBackend<'a> {
+    type ErrorType = DynBackendError;
+
+    fn get_size(&self) -> (u32, u32) {
+        self.inner.size()
+    }
+
+    // The backend underneath is prepared and presented by its own drawing area
+    fn ensure_prepared(&mut self) -> DynResult {
+        Ok(())
+    }
+
+    fn present(&mut self) -> DynResult {
+        Ok(())
+    }
+
+    fn draw_pixel(&mut self, point: BackendCoord, color: BackendColor) -> DynResult {
+        self.inner.pixel(point, color)
+    }
+
+    fn draw_line<S: BackendStyle>(
+        &mut self,
+        from: BackendCoord,
+        to: BackendCoord,
+        style: &S,
+    ) -> DynResult {
+        self.inner.line(from, to, DynStyle::of(style))
+    }
+
+    fn draw_rect<S: BackendStyle>(
+        &mut self,
+        upper_left: BackendCoord,
+        bottom_right: BackendCoord,
+        style: &S,
+        fill: bool,
+    ) -> DynResult {
+        self.inner
+            .rect(upper_left, bottom_right, DynStyle::of(style), fill)
+    }
+
+    fn draw_path<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        path: I,
+        style: &S,
+    ) -> DynResult {
+        self.inner
+            .path(path.into_iter().collect(), DynStyle::of(style))
+    }
+
+    fn draw_circle<S: BackendStyle>(
+        &mut self,
+        center: BackendCoord,
+        radius: u32,
+        style: &S,
+        fill: bool,
+    ) -> DynResult {
+        self.inner.circle(center, radius, DynStyle::of(style), fill)
+    }
+
+    fn fill_polygon<S: BackendStyle, I: IntoIterator<Item = BackendCoord>>(
+        &mut self,
+        vert: I,
+        style: &S,
+    ) -> DynResult {
+        self.inner
+            .polygon(vert.into_iter().collect(), DynStyle::of(style))
+    }
+}
+
+// Element covering a whole drawing area which lends its backend to `draw`
+struct DynLayer<F> {
+    corners: [BackendCoord; 2],
+    draw: F,
+}
+
+impl<'a, F> PointCollection<'a, BackendCoord> for &'a DynLayer<F> {
+    type Point = &'a BackendCoord;
+    type IntoIter = &'a [BackendCoord];
+    fn point_iter(self) -> &'a [BackendCoord] {
+        &self.corners
+    }
+}
+
+impl<DB: DrawingBackend, F: Fn(&DrawingArea<DynBackend, Shift>)> Drawable<DB> for DynLayer<F> {
+    fn draw<I: Iterator<Item = BackendCoord>>(
+        &self,
+        mut points: I,
+        backend: &mut DB,
+        _: (u32, u32),
+    ) -> Result<(), DrawingErrorKind<DB::ErrorType>> {
+        if let (Some(ul), Some(br)) = (points.next(), points.next()) {
+            let area = DynBackend { inner: backend }
+                .into_drawing_area()
+                .shrink(ul, (br.0 - ul.0, br.1 - ul.1));
+            (self.draw)(&area);
+        }
+        Ok(())
+    }
+}
+
+// Runs `draw` on the same part of the same backend as `drawing_area`, seen
+// through a `DynBackend`
+pub fn draw_dyn<DB: DrawingBackend, F: Fn(&DrawingArea<DynBackend, Shift>)>(
+    drawing_area: &DrawingArea<DB, Shift>,
+    draw: F,
+) {
+    let (width, height) = drawing_area.dim_in_pixel();
+    let layer = DynLayer {
+        corners: [(0, 0), (width as i32, height as i32)],
+        draw,
+    };
+    drawing_area.draw(&layer).unwrap();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,4 +573,68 @@ mod tests {
         ))
         .unwrap();
     }
+
+    #[test]
+    fn test_error_ellipse_axes() {
+        // confidence of one sigma: (-2 ln(1 - p))^0.5 = 1
+        let p = 1.0 - (-0.5f64).exp();
+        let cov = ndarray::arr2(&[[4.0, 0.0], [0.0, 1.0]]);
+        let ellipse = ErrorEllipse::new((1.0, -1.0), &cov, p, BLUE);
+        let first = ellipse.points[0];
+        let quarter = ellipse.points[ARC_SEGMENTS / 4];
+        assert!((first.0 - 3.0).abs() < 1e-5 && (first.1 + 1.0).abs() < 1e-5);
+        assert!((quarter.0 - 1.0).abs() < 1e-5 && quarter.1.abs() < 1e-5);
+        assert_eq!(ellipse.points.first(), ellipse.points.last());
+
+        // the long axis follows the correlation
+        let cov = ndarray::arr2(&[[1.0, 0.9], [0.9, 1.0]]);
+        let ellipse = ErrorEllipse::new((0.0, 0.0), &cov, p, BLUE);
+        let (x, y) = ellipse.points[0];
+        assert!((x - y).abs() < 1e-5 && (x * x + y * y - 1.9).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_draw_field_of_view_and_robot() {
+        let mut buffer = vec![255u8; 100 * 100 * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut buffer, (100, 100)).into_drawing_area();
+            let to_pixel =
+                |(x, y): (f32, f32)| ((50.0 + x * 10.0) as i32, (50.0 - y * 10.0) as i32);
+            let fov = FieldOfView::new((0.0, 0.0, 0.0), (1.0, 4.0), (-0.5, 0.5), BLUE.filled())
+                .map(to_pixel);
+            root.draw(&fov).unwrap();
+            let robot = RobotGlyph::new((-3.0, 0.0, 0.0), 1.0, RED.filled()).map(to_pixel);
+            root.draw(&robot).unwrap();
+            root.present().unwrap();
+        }
+        let pixel = |x: usize, y: usize| &buffer[(y * 100 + x) * 3..(y * 100 + x) * 3 + 3];
+        // inside the wedge, in front of the sensor and behind it
+        assert_eq!(pixel(75, 50), &[0, 0, 255]);
+        assert_eq!(pixel(53, 50), &[255, 255, 255]);
+        assert_eq!(pixel(45, 50), &[255, 255, 255]);
+        // body of the robot and its front line
+        assert_eq!(pixel(20, 47), &[255, 0, 0]);
+        assert_eq!(pixel(25, 50), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn test_draw_dyn_keeps_the_area() {
+        let mut buffer = vec![255u8; 100 * 100 * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut buffer, (100, 100)).into_drawing_area();
+            let area = root.clone().shrink((50, 20), (50, 80));
+            draw_dyn(&area, |area| {
+                assert_eq!(area.dim_in_pixel(), (50, 80));
+                area.draw_pixel((0, 0), &BLUE).unwrap();
+                area.draw(&Rectangle::new([(10, 10), (20, 20)], BLUE.filled()))
+                    .unwrap();
+            });
+            root.present().unwrap();
+        }
+        let pixel = |x: usize, y: usize| &buffer[(y * 100 + x) * 3..(y * 100 + x) * 3 + 3];
+        assert_eq!(pixel(50, 20), &[0, 0, 255]);
+        assert_eq!(pixel(65, 35), &[0, 0, 255]);
+        assert_eq!(pixel(0, 0), &[255, 255, 255]);
+        assert_eq!(pixel(15, 15), &[255, 255, 255]);
+    }
 }
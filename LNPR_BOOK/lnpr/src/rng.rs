@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+// Random number generator shared by the world and its robots, sensors and
+// estimators. Clones are handles to the same stream, so a single seed makes the
+// whole simulation reproducible.
+#[derive(Clone, Debug)]
+pub struct SimRng {
+    inner: Rc<RefCell<StdRng>>,
+}
+
+impl SimRng {
+    pub fn seed_from_u64(seed: u64) -> Self {
+        SimRng {
+            inner: Rc::new(RefCell::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    pub fn from_entropy() -> Self {
+        SimRng {
+            inner: Rc::new(RefCell::new(StdRng::from_entropy())),
+        }
+    }
+
+    // Restarts the stream of every handle sharing this generator
+    pub fn reseed(&self, seed: u64) {
+        *self.inner.borrow_mut() = StdRng::seed_from_u64(seed);
+    }
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        SimRng::from_entropy()
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        self.inner.borrow_mut().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.inner.borrow_mut().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.borrow_mut().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.inner.borrow_mut().try_fill_bytes(dest)
+    }
+}
+
+// `mvtnorm` samples through ndarray-rand, which is built on an older rand
+impl ndarray_rand::rand::RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        RngCore::next_u32(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RngCore::next_u64(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RngCore::fill_bytes(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), ndarray_rand::rand::Error> {
+        RngCore::fill_bytes(self, dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::*;
+    use crate::mcl::*;
+    use crate::robot::*;
+    use crate::test_util::*;
+    use plotters::prelude::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_handles_share_stream() {
+        let mut a = SimRng::seed_from_u64(1);
+        let mut b = a.clone();
+        let first = RngCore::next_u64(&mut a);
+        assert_ne!(RngCore::next_u64(&mut b), first);
+
+        b.reseed(1);
+        assert_eq!(RngCore::next_u64(&mut a), first);
+    }
+
+    // True trajectory followed by the final estimate of MCL
+    fn simulate(seed: u64) -> Vec<(f32, f32, f32)> {
+        let map = landmarks();
+        let cov = motion_noise_cov();
+
+        let mut world = World::new(map.clone(), 5, 5, 3.0, 0.1).set_seed(seed);
+        let mcl = Mcl::new(map.clone(), (0.0, 0.0, 0.0), 20, cov);
+        let agent = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, mcl);
+        let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0)).set_phantom(
+            0.1,
+            (-5.0, 5.0),
+            (-5.0, 5.0),
+        );
+        let robot = Robot::new((0.0, 0.0, 0.0), &BLACK, agent, camera).set_kidnap(
+            1.0,
+            (-5.0, 5.0),
+            (-5.0, 5.0),
+        );
+        world.push(Box::new(robot));
+        world.run();
+
+        let robot = world
+            .object::<Robot<EstimateAgent<Mcl>, Camera>>(0)
+            .unwrap();
+        let mut poses = robot.poses.clone();
+        poses.push(robot.agent.estimator.pose());
+        poses
+    }
+
+    #[test]
+    fn test_same_seed_same_trajectory() {
+        assert_eq!(simulate(3), simulate(3));
+        assert_ne!(simulate(3), simulate(4));
+    }
+}
@@ -1,19 +1,28 @@
+use plotters::coord::types::RangedCoordi32;
 use plotters::prelude::*;
 use rand_distr::{Distribution, Exp, Normal, Uniform};
+use std::any::Any;
 use std::f32::consts::PI;
 
 use crate::base::*;
+use crate::collision::*;
+use crate::log::*;
+use crate::plotters_ext::DynBackend;
+use crate::rng::*;
 
 // Robot
 #[derive(Clone)]
-pub struct Robot<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> {
+pub struct Robot<AT: AgentTrait, OS: OpticalSensor> {
     pub pose: (f32, f32, f32),
-    pub color: &'a C,
+    pub color: RGBAColor,
     pub agent: AT,
     pub sensor: OS,
     pub poses: Vec<(f32, f32, f32)>,
+    pub log: Log,
+    pub radius: f32,
     pub bias_rate_nu: f32,
     pub bias_rate_omega: f32,
+    bias_rate_stds: (f32, f32),
     noise_pdf: Exp<f32>,
     theta_noise: Normal<f32>,
     stuck_pdf: Exp<f32>,
@@ -27,10 +36,11 @@ pub struct Robot<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> {
     kidnap_dist_x: Uniform<f32>,
     kidnap_dist_y: Uniform<f32>,
     kidnap_dist_o: Uniform<f32>,
+    rng: SimRng,
 }
 
-impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
-    pub fn new(pose: (f32, f32, f32), color: &'a C, agent: AT, sensor: OS) -> Self {
+impl<AT: AgentTrait, OS: OpticalSensor> Robot<AT, OS> {
+    pub fn new<C: Color>(pose: (f32, f32, f32), color: &C, agent: AT, sensor: OS) -> Self {
         let noise_per_meter = 5.0;
         let noise_std = PI / 60.0;
         let bias_rate_stds = (0.1, 0.1);
@@ -40,7 +50,8 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
         let kidnap_range_x = (-5.0, 5.0);
         let kidnap_range_y = (-5.0, 5.0);
 
-        let mut r = rand::thread_rng();
+        let rng = SimRng::default();
+        let mut r = rng.clone();
         let pdf = Exp::new(1.0 / (1e-100 + noise_per_meter)).unwrap();
         let distance_until_noise = pdf.sample(&mut r);
         let theta_noise = Normal::new(0.0, noise_std).unwrap();
@@ -62,15 +73,18 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
 
         Robot {
             pose: pose,
-            color: color,
+            color: color.to_rgba(),
             agent: agent,
             sensor: sensor,
             poses: vec![pose],
+            log: Log::default(),
+            radius: ROBOT_RADIUS,
             noise_pdf: pdf,
             distance_until_noise: distance_until_noise,
             theta_noise: theta_noise,
             bias_rate_nu: bias_rate_nu,
             bias_rate_omega: bias_rate_omega,
+            bias_rate_stds,
             stuck_pdf: stuck_pdf,
             escape_pdf: escape_pdf,
             time_until_stuck: time_until_stuck,
@@ -81,11 +95,39 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
             kidnap_dist_x: kidnap_dist_x,
             kidnap_dist_y: kidnap_dist_y,
             kidnap_dist_o: kidnap_dist_o,
+            rng,
         }
     }
 
+    pub fn set_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    // Draws from `rng` from now on. The bias and the time until the next
+    // noise, stuck and kidnap events are sampled again from it.
+    pub fn set_rng(mut self, rng: SimRng) -> Self {
+        self.reseed(rng);
+        self
+    }
+
+    fn reseed(&mut self, rng: SimRng) {
+        let mut r = rng.clone();
+        self.distance_until_noise = self.noise_pdf.sample(&mut r);
+        self.bias_rate_nu = Normal::new(1.0, self.bias_rate_stds.0)
+            .unwrap()
+            .sample(&mut r);
+        self.bias_rate_omega = Normal::new(1.0, self.bias_rate_stds.1)
+            .unwrap()
+            .sample(&mut r);
+        self.time_until_stuck = self.stuck_pdf.sample(&mut r);
+        self.time_until_escape = self.escape_pdf.sample(&mut r);
+        self.time_until_kidnap = self.kidnap_pdf.sample(&mut r);
+        self.rng = rng;
+    }
+
     pub fn set_noise(mut self, noise_per_meter: f32, noise_std: f32) -> Self {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let pdf = Exp::new(1.0 / (1e-100 + noise_per_meter)).unwrap();
         let distance_until_noise = pdf.sample(&mut r);
         let theta_noise = Normal::new(0.0, noise_std).unwrap();
@@ -97,17 +139,18 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
     }
 
     pub fn set_bias(mut self, bias_rate_stds: (f32, f32)) -> Self {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let bias_rate_nu = Normal::new(1.0, bias_rate_stds.0).unwrap().sample(&mut r);
         let bias_rate_omega = Normal::new(1.0, bias_rate_stds.1).unwrap().sample(&mut r);
 
         self.bias_rate_nu = bias_rate_nu;
         self.bias_rate_omega = bias_rate_omega;
+        self.bias_rate_stds = bias_rate_stds;
         self
     }
 
     pub fn set_stuck(mut self, expected_stuck_time: f32, expected_escape_time: f32) -> Self {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let stuck_pdf = Exp::new(1.0 / (1e-100 + expected_stuck_time)).unwrap();
         let escape_pdf = Exp::new(1.0 / (1e-100 + expected_escape_time)).unwrap();
 
@@ -127,7 +170,7 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
         kidnap_range_x: (f32, f32),
         kidnap_range_y: (f32, f32),
     ) -> Self {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let kidnap_pdf = Exp::new(1.0 / (1e-100 + expected_kidnap_time)).unwrap();
         let time_until_kidnap = kidnap_pdf.sample(&mut r);
         let kidnap_dist_x = Uniform::from(kidnap_range_x.0..kidnap_range_x.1);
@@ -152,7 +195,7 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
         let round = 0.2;
         self.distance_until_noise -= nu.abs() * time_interval + round * omega.abs() * time_interval;
         if self.distance_until_noise <= 0.0 {
-            let mut r = rand::thread_rng();
+            let mut r = self.rng.clone();
             self.distance_until_noise += self.noise_pdf.sample(&mut r);
             pose.2 += self.theta_noise.sample(&mut r);
         }
@@ -167,14 +210,14 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
         if self.is_stuck {
             self.time_until_escape -= time_interval;
             if self.time_until_escape <= 0.0 {
-                let mut r = rand::thread_rng();
+                let mut r = self.rng.clone();
                 self.time_until_escape += self.escape_pdf.sample(&mut r);
                 self.is_stuck = false;
             }
         } else {
             self.time_until_stuck -= time_interval;
             if self.time_until_stuck <= 0.0 {
-                let mut r = rand::thread_rng();
+                let mut r = self.rng.clone();
                 self.time_until_stuck += self.stuck_pdf.sample(&mut r);
                 self.is_stuck = true;
             }
@@ -187,7 +230,7 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
     fn kidnap(&mut self, pose: (f32, f32, f32), time_interval: f32) -> (f32, f32, f32) {
         self.time_until_kidnap -= time_interval;
         if self.time_until_kidnap <= 0.0 {
-            let mut r = rand::thread_rng();
+            let mut r = self.rng.clone();
             self.time_until_kidnap += self.kidnap_pdf.sample(&mut r);
             let x = self.kidnap_dist_x.sample(&mut r);
             let y = self.kidnap_dist_y.sample(&mut r);
@@ -198,25 +241,11 @@ impl<'a, AT: AgentTrait, OS: OpticalSensor, C: Color> Robot<'a, AT, OS, C> {
     }
 }
 
-impl<'a, AT: AgentTrait + Clone, OS: OpticalSensor + Clone, C: 'a + Color> Robotize<'a, AT, OS, C>
-    for Robot<'a, AT, OS, C>
-{
+impl<AT: AgentTrait + 'static, OS: OpticalSensor + 'static> Robotize for Robot<AT, OS> {
     fn pose(&self) -> (f32, f32, f32) {
         self.pose
     }
 
-    fn color(&self) -> &'a C {
-        self.color.clone()
-    }
-
-    fn agent(&self) -> AT {
-        self.agent.clone()
-    }
-
-    fn sensor(&self) -> OS {
-        self.sensor.clone()
-    }
-
     fn poses(&self) -> Vec<(f32, f32, f32)> {
         self.poses.clone()
     }
@@ -226,10 +255,20 @@ impl<'a, AT: AgentTrait + Clone, OS: OpticalSensor + Clone, C: 'a + Color> Robot
     }
 
     fn one_step(&mut self, time_interval: f32) {
-        let obs = self.sensor.data(self.pose);
+        let pose = self.pose;
+        let obs = self.sensor.data(pose);
         let decision = self.agent.decision(obs);
         let biased = self.bias(decision.0, decision.1);
         let (nu, omega) = self.stuck(biased.0, biased.1, time_interval);
+        self.log.time_interval = time_interval;
+        self.log.steps.push(LogStep {
+            pose,
+            command: decision,
+            velocity: (nu, omega),
+            observations: self.sensor.lastdata(),
+            overlooked: self.sensor.overlooked(),
+            collisions: Vec::new(),
+        });
         self._state_transition(nu, omega, time_interval);
         self.pose = self.noise(self.pose, nu, omega, time_interval);
         self.pose = self.kidnap(self.pose, time_interval);
@@ -237,17 +276,85 @@ impl<'a, AT: AgentTrait + Clone, OS: OpticalSensor + Clone, C: 'a + Color> Robot
     }
 
     fn _state_transition(&mut self, nu: f32, omega: f32, time: f32) {
-        self.pose = IdealRobot::<Agent, IdealCamera, RGBColor>::state_transition(
-            nu, omega, time, self.pose,
+        self.pose = IdealRobot::<Agent, IdealCamera>::state_transition(nu, omega, time, self.pose);
+    }
+
+    fn estimate(&self) -> Option<Estimate> {
+        self.agent.estimate()
+    }
+
+    fn estimated_map(&self) -> Option<Map> {
+        self.agent.estimated_map()
+    }
+
+    fn log(&self) -> Option<&Log> {
+        Some(&self.log)
+    }
+
+    fn replay(&mut self, log: &Log) -> Option<Vec<Estimate>> {
+        self.agent.replay(log)
+    }
+
+    fn draw(
+        &self,
+        drawing_area: &DrawingArea<DynBackend, Cartesian2d<RangedCoordi32, RangedCoordi32>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        draw_robot(
+            drawing_area,
+            xlim,
+            ylim,
+            &self.color,
+            &self.agent,
+            &self.sensor,
+            &self.poses,
+            self.radius,
         );
     }
+
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn set_pose(&mut self, pose: (f32, f32, f32)) {
+        self.pose = pose;
+        if let Some(last) = self.poses.last_mut() {
+            *last = pose;
+        }
+    }
+
+    fn record_collisions(&mut self, collisions: &[Collision]) {
+        if let Some(step) = self.log.steps.last_mut() {
+            step.collisions.extend_from_slice(collisions);
+        }
+    }
+
+    fn set_others(&mut self, others: &[Footprint]) {
+        self.sensor.set_obstructions(others);
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        self.reseed(rng.clone());
+        self.agent.set_rng(rng.clone());
+        self.sensor.set_rng(rng);
+    }
+
+    fn lastdata(&self) -> Vec<Observation> {
+        self.sensor.lastdata()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
 // Camera
 #[derive(Clone)]
 pub struct Camera {
     pub map: Map,
-    pub lastdata: Vec<(f32, f32)>,
+    pub lastdata: Vec<Observation>,
+    pub lastoverlooked: Vec<Observation>,
     pub distance_range: (f32, f32),
     pub direction_range: (f32, f32),
     pub distance_noise_rate: f32,
@@ -258,12 +365,17 @@ pub struct Camera {
     phantom_dist_x: Uniform<f32>,
     phantom_dist_y: Uniform<f32>,
     pub oversight_prob: f32,
-    pub occlusion_prob: f32,
+    // bodies of the other robots; they and the walls and obstacles of the
+    // map hide the landmarks behind them
+    pub obstructions: Vec<Footprint>,
+    bias_rate_stds: (f32, f32),
+    rng: SimRng,
 }
 
 impl Camera {
     pub fn new(map: Map, distance_range: (f32, f32), direction_range: (f32, f32)) -> Self {
-        let mut r = rand::thread_rng();
+        let rng = SimRng::default();
+        let mut r = rng.clone();
         let distance_noise_rate = 0.1;
         let direction_noise = PI / 90.0;
         let distance_bias_rate_std = 0.1;
@@ -272,7 +384,6 @@ impl Camera {
         let phantom_range_x = (-5.0, 5.0);
         let phantom_range_y = (-5.0, 5.0);
         let oversight_prob = 0.1;
-        let occlusion_prob = 0.0;
 
         let distance_bias = Normal::new(0.0, distance_bias_rate_std)
             .unwrap()
@@ -287,6 +398,7 @@ impl Camera {
         Camera {
             map: map,
             lastdata: Vec::new(),
+            lastoverlooked: Vec::new(),
             distance_range: distance_range,
             direction_range: direction_range,
             distance_noise_rate: distance_noise_rate,
@@ -297,10 +409,18 @@ impl Camera {
             phantom_dist_x: phantom_dist_x,
             phantom_dist_y: phantom_dist_y,
             oversight_prob: oversight_prob,
-            occlusion_prob: occlusion_prob,
+            obstructions: Vec::new(),
+            bias_rate_stds: (distance_bias_rate_std, direction_bias_rate_std),
+            rng,
         }
     }
 
+    // Draws from `rng` from now on. The bias is sampled again from it.
+    pub fn set_rng(mut self, rng: SimRng) -> Self {
+        OpticalSensor::set_rng(&mut self, rng);
+        self
+    }
+
     pub fn set_noise(mut self, distance_noise_rate: f32, direction_noise: f32) -> Self {
         self.distance_noise_rate = distance_noise_rate;
         self.direction_noise = direction_noise;
@@ -308,7 +428,7 @@ impl Camera {
     }
 
     pub fn set_bias(mut self, distance_bias_rate_std: f32, direction_bias_rate_std: f32) -> Self {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let distance_bias = Normal::new(0.0, distance_bias_rate_std)
             .unwrap()
             .sample(&mut r);
@@ -317,6 +437,7 @@ impl Camera {
             .sample(&mut r);
         self.distance_bias = distance_bias;
         self.direction_bias = direction_bias;
+        self.bias_rate_stds = (distance_bias_rate_std, direction_bias_rate_std);
         self
     }
 
@@ -339,13 +460,8 @@ impl Camera {
         self
     }
 
-    pub fn set_occlusion(mut self, occlusion_prob: f32) -> Self {
-        self.occlusion_prob = occlusion_prob;
-        self
-    }
-
     fn noise(&self, relpos: (f32, f32)) -> (f32, f32) {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let ell = Normal::new(relpos.0, relpos.0 * self.distance_noise_rate)
             .unwrap()
             .sample(&mut r);
@@ -362,22 +478,22 @@ impl Camera {
         )
     }
 
-    fn phantom(&self, cam_pose: (f32, f32, f32), relpos: (f32, f32)) -> (f32, f32) {
-        let mut r = rand::thread_rng();
+    fn phantom(&self, cam_pose: (f32, f32, f32), relpos: (f32, f32)) -> ((f32, f32), bool) {
+        let mut r = self.rng.clone();
         let dice = Uniform::from(0.0..1.0).sample(&mut r);
         if dice < self.phantom_prob {
             let pos = (
                 self.phantom_dist_x.sample(&mut r),
                 self.phantom_dist_y.sample(&mut r),
             );
-            Camera::obs_fn(cam_pose, pos)
+            (Camera::obs_fn(cam_pose, pos), true)
         } else {
-            relpos
+            (relpos, false)
         }
     }
 
     fn oversight(&self, relpos: (f32, f32)) -> Option<(f32, f32)> {
-        let mut r = rand::thread_rng();
+        let mut r = self.rng.clone();
         let dice = Uniform::from(0.0..1.0).sample(&mut r);
         if dice < self.oversight_prob {
             None
@@ -385,18 +501,6 @@ impl Camera {
             Some(relpos)
         }
     }
-
-    fn occlusion(&self, relpos: (f32, f32)) -> (f32, f32) {
-        let mut r = rand::thread_rng();
-        let dice = Uniform::from(0.0..1.0).sample(&mut r);
-        if dice < self.occlusion_prob {
-            let random = Uniform::from(0.0..1.0).sample(&mut r);
-            let ell = relpos.0 + random * (self.distance_range.1 - relpos.0);
-            (ell, relpos.1)
-        } else {
-            relpos
-        }
-    }
 }
 
 impl OpticalSensor for Camera {
@@ -404,7 +508,7 @@ impl OpticalSensor for Camera {
         self.map.clone()
     }
 
-    fn lastdata(&self) -> Vec<(f32, f32)> {
+    fn lastdata(&self) -> Vec<Observation> {
         self.lastdata.clone()
     }
 
@@ -416,24 +520,53 @@ impl OpticalSensor for Camera {
         self.direction_range
     }
 
-    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<(f32, f32)> {
-        let observed = self
-            .map
-            .landmarks
-            .iter()
-            .map(|l| {
-                self.occlusion(self.phantom(cam_pose, Self::obs_fn(cam_pose, l.clone().position)))
-            })
-            .map(|pos| self.oversight(pos))
-            .filter(|pos| pos.is_some())
-            .map(|pos| pos.unwrap())
-            .filter(|pos| self.visible(*pos))
-            .map(|pos| self.bias(self.noise(pos)))
-            .collect::<Vec<(f32, f32)>>();
+    fn data(&mut self, cam_pose: (f32, f32, f32)) -> &Vec<Observation> {
+        let mut observed = Vec::new();
+        let mut overlooked = Vec::new();
+        let position = (cam_pose.0, cam_pose.1);
+        for l in self.map.landmarks.iter() {
+            if !in_line_of_sight(&self.map, &self.obstructions, position, l.position) {
+                continue;
+            }
+            let (pos, is_phantom) = self.phantom(cam_pose, Self::obs_fn(cam_pose, l.position));
+            let obs = Observation {
+                pos,
+                id: l.id,
+                is_phantom,
+            };
+            match self.oversight(obs.pos) {
+                Some(pos) if self.visible(pos) => observed.push(Observation {
+                    pos: self.bias(self.noise(pos)),
+                    ..obs
+                }),
+                None if self.visible(obs.pos) => overlooked.push(obs),
+                _ => {}
+            }
+        }
         self.lastdata = observed;
+        self.lastoverlooked = overlooked;
         &self.lastdata
     }
 
+    fn overlooked(&self) -> Vec<Observation> {
+        self.lastoverlooked.clone()
+    }
+
+    fn set_obstructions(&mut self, obstructions: &[Footprint]) {
+        self.obstructions = obstructions.to_vec();
+    }
+
+    fn set_rng(&mut self, rng: SimRng) {
+        let mut r = rng.clone();
+        self.distance_bias = Normal::new(0.0, self.bias_rate_stds.0)
+            .unwrap()
+            .sample(&mut r);
+        self.direction_bias = Normal::new(0.0, self.bias_rate_stds.1)
+            .unwrap()
+            .sample(&mut r);
+        self.rng = rng;
+    }
+
     fn obs_fn(cam_pose: (f32, f32, f32), obj_pos: (f32, f32)) -> (f32, f32) {
         let diff = (obj_pos.0 - cam_pose.0, obj_pos.1 - cam_pose.1);
         let mut phi = diff.1.atan2(diff.0) - cam_pose.2;
@@ -448,4 +581,15 @@ impl OpticalSensor for Camera {
         let distance = (diff.0.powi(2) + diff.1.powi(2)).sqrt();
         (distance, phi)
     }
+
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
+        &self,
+        cam_pose: (f32, f32, f32),
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
+        xlim: i32,
+        ylim: i32,
+    ) {
+        draw_field_of_view(self, cam_pose, drawing_area, xlim, ylim);
+        draw_observations(&self.lastdata, cam_pose, drawing_area, xlim, ylim);
+    }
 }
@@ -0,0 +1,468 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use ndarray::{arr1, Array2};
+use plotters::prelude::*;
+use serde::Deserialize;
+
+use crate::base::*;
+use crate::collision::*;
+use crate::ekf_slam::*;
+use crate::fast_slam::*;
+use crate::graph_slam::*;
+use crate::kf::*;
+use crate::mcl::*;
+use crate::robot::*;
+
+// Description of a whole simulation, usually read from a TOML file:
+//
+//     landmarks = [[-4.0, 2.0], [2.0, -3.0]]
+//     walls = [[[-5.0, -5.0], [-5.0, 5.0]]]
+//     obstacles = [[[1.0, 1.0], [2.0, 1.0], [2.0, 2.0]]]
+//
+//     [world]
+//     time_span = 30.0
+//     time_interval = 0.1
+//
+//     [[robots]]
+//     type = "noisy"
+//     pose = [0.0, 0.0, 0.0]
+//     agent = { nu = 0.2, omega = 0.1745, estimator = { type = "mcl" } }
+//     camera = { type = "noisy", oversight = 0.0 }
+//
+// Settings left out keep the defaults of the corresponding constructor.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+    pub world: WorldSpec,
+    #[serde(default)]
+    pub landmarks: Vec<(f32, f32)>,
+    // (from, to) of each wall
+    #[serde(default)]
+    pub walls: Vec<((f32, f32), (f32, f32))>,
+    // vertices of each obstacle
+    #[serde(default)]
+    pub obstacles: Vec<Vec<(f32, f32)>>,
+    #[serde(default)]
+    pub robots: Vec<RobotSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WorldSpec {
+    #[serde(default = "default_lim")]
+    pub xlim: i32,
+    #[serde(default = "default_lim")]
+    pub ylim: i32,
+    pub time_span: f32,
+    pub time_interval: f32,
+    pub seed: Option<u64>,
+    // "stop", "slide" or "flag"; robots go through everything if left out
+    pub collision: Option<CollisionResponse>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    #[default]
+    Ideal,
+    Noisy,
+}
+
+// An ideal robot ignores every noise setting
+#[derive(Clone, Debug, Deserialize)]
+pub struct RobotSpec {
+    #[serde(rename = "type", default)]
+    pub kind: Kind,
+    pub pose: (f32, f32, f32),
+    #[serde(default)]
+    pub color: (u8, u8, u8),
+    #[serde(default = "default_radius")]
+    pub radius: f32,
+    pub agent: AgentSpec,
+    #[serde(default)]
+    pub camera: CameraSpec,
+    // (noise_per_meter, noise_std)
+    pub noise: Option<(f32, f32)>,
+    // Standard deviations of the rates of (nu, omega)
+    pub bias: Option<(f32, f32)>,
+    // (expected_stuck_time, expected_escape_time)
+    pub stuck: Option<(f32, f32)>,
+    pub kidnap: Option<KidnapSpec>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct KidnapSpec {
+    pub expected_time: f32,
+    pub range_x: (f32, f32),
+    pub range_y: (f32, f32),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AgentSpec {
+    pub nu: f32,
+    pub omega: f32,
+    pub estimator: Option<EstimatorSpec>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimatorKind {
+    Mcl,
+    Kf,
+    EkfSlam,
+    FastSlam,
+    GraphSlam,
+}
+
+// Estimators start from the pose of their robot
+#[derive(Clone, Debug, Deserialize)]
+pub struct EstimatorSpec {
+    #[serde(rename = "type")]
+    pub kind: EstimatorKind,
+    #[serde(default = "default_particles")]
+    pub particles: usize,
+    // FastSLAM 1.0 or 2.0
+    #[serde(default = "default_version")]
+    pub version: u8,
+    // Standard deviations of nu per nu, nu per omega, omega per nu and
+    // omega per omega
+    #[serde(default = "default_motion_noise")]
+    pub motion_noise: [f64; 4],
+    // (distance_dev_rate, direction_dev)
+    #[serde(default = "default_observation_noise")]
+    pub observation_noise: (f32, f32),
+    // Cell size of the belief heatmap drawn by MCL
+    pub heatmap: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CameraSpec {
+    #[serde(rename = "type", default)]
+    pub kind: Kind,
+    #[serde(default = "default_distance_range")]
+    pub distance_range: (f32, f32),
+    #[serde(default = "default_direction_range")]
+    pub direction_range: (f32, f32),
+    // (distance_noise_rate, direction_noise)
+    pub noise: Option<(f32, f32)>,
+    // (distance_bias_rate_std, direction_bias_rate_std)
+    pub bias: Option<(f32, f32)>,
+    pub phantom: Option<PhantomSpec>,
+    pub oversight: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PhantomSpec {
+    pub prob: f32,
+    pub range_x: (f32, f32),
+    pub range_y: (f32, f32),
+}
+
+impl Default for CameraSpec {
+    fn default() -> Self {
+        CameraSpec {
+            kind: Kind::Ideal,
+            distance_range: default_distance_range(),
+            direction_range: default_direction_range(),
+            noise: None,
+            bias: None,
+            phantom: None,
+            oversight: None,
+        }
+    }
+}
+
+fn default_lim() -> i32 {
+    5
+}
+
+fn default_radius() -> f32 {
+    ROBOT_RADIUS
+}
+
+fn default_particles() -> usize {
+    100
+}
+
+fn default_version() -> u8 {
+    1
+}
+
+fn default_motion_noise() -> [f64; 4] {
+    [0.19, 0.001, 0.13, 0.2]
+}
+
+fn default_observation_noise() -> (f32, f32) {
+    (0.14, 0.05)
+}
+
+fn default_distance_range() -> (f32, f32) {
+    (0.5, 6.0)
+}
+
+fn default_direction_range() -> (f32, f32) {
+    (-PI / 3.0, PI / 3.0)
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "cannot read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "invalid scenario: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ScenarioError {
+    fn from(e: toml::de::Error) -> Self {
+        ScenarioError::Parse(e)
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = ScenarioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+impl Scenario {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn map(&self) -> Map {
+        let mut map = Map::new();
+        for ln in self.landmarks.iter() {
+            map.append_landmark(*ln);
+        }
+        for (from, to) in self.walls.iter() {
+            map.append_wall(*from, *to);
+        }
+        for vertices in self.obstacles.iter() {
+            map.append_obstacle(vertices.clone());
+        }
+        map
+    }
+
+    // World with every robot of the scenario. All of them draw from the
+    // random number generator of the world.
+    pub fn build(&self) -> World {
+        let map = self.map();
+        let spec = &self.world;
+        let mut world = World::new(
+            map.clone(),
+            spec.xlim,
+            spec.ylim,
+            spec.time_span,
+            spec.time_interval,
+        );
+        if let Some(seed) = spec.seed {
+            world = world.set_seed(seed);
+        }
+        if let Some(response) = spec.collision {
+            world = world.set_collision(response);
+        }
+        for robot in self.robots.iter() {
+            world.push(robot.build(&map, spec.time_interval));
+        }
+        world
+    }
+}
+
+impl EstimatorSpec {
+    fn motion_noise_cov(&self) -> Array2<f64> {
+        let stds = arr1(&self.motion_noise);
+        Array2::from_diag(&(&stds * &stds))
+    }
+}
+
+impl CameraSpec {
+    fn noisy(&self, map: &Map) -> Camera {
+        let mut camera = Camera::new(map.clone(), self.distance_range, self.direction_range);
+        if let Some((rate, dev)) = self.noise {
+            camera = camera.set_noise(rate, dev);
+        }
+        if let Some((distance, direction)) = self.bias {
+            camera = camera.set_bias(distance, direction);
+        }
+        if let Some(p) = &self.phantom {
+            camera = camera.set_phantom(p.prob, p.range_x, p.range_y);
+        }
+        if let Some(prob) = self.oversight {
+            camera = camera.set_oversight(prob);
+        }
+        camera
+    }
+}
+
+impl RobotSpec {
+    // The robot draws from the generator of the world it is pushed into
+    pub fn build(&self, map: &Map, time_interval: f32) -> Box<dyn Robotize> {
+        let (nu, omega) = (self.agent.nu, self.agent.omega);
+        let e = match &self.agent.estimator {
+            Some(e) => e,
+            None => return self.with_agent(Agent { nu, omega }, map),
+        };
+
+        let (pose, cov) = (self.pose, e.motion_noise_cov());
+        let (rate, dev) = e.observation_noise;
+        match e.kind {
+            EstimatorKind::Mcl => {
+                let mut mcl =
+                    Mcl::new(map.clone(), pose, e.particles, cov).set_observation_noise(rate, dev);
+                if let Some(cell) = e.heatmap {
+                    mcl = mcl.set_heatmap(cell);
+                }
+                self.with_agent(EstimateAgent::new(nu, omega, time_interval, mcl), map)
+            }
+            EstimatorKind::Kf => {
+                let kf = KalmanFilter::new(map.clone(), pose, cov).set_observation_noise(rate, dev);
+                self.with_agent(EstimateAgent::new(nu, omega, time_interval, kf), map)
+            }
+            EstimatorKind::EkfSlam => {
+                let slam = EkfSlam::new(pose, cov).set_observation_noise(rate, dev);
+                self.with_agent(EstimateAgent::new(nu, omega, time_interval, slam), map)
+            }
+            EstimatorKind::FastSlam => {
+                let version = if e.version == 2 {
+                    FastSlamVersion::Two
+                } else {
+                    FastSlamVersion::One
+                };
+                let slam = FastSlam::new(pose, e.particles, cov)
+                    .set_observation_noise(rate, dev)
+                    .set_version(version);
+                self.with_agent(EstimateAgent::new(nu, omega, time_interval, slam), map)
+            }
+            EstimatorKind::GraphSlam => {
+                let slam = GraphSlam::new(pose, cov).set_observation_noise(rate, dev);
+                self.with_agent(EstimateAgent::new(nu, omega, time_interval, slam), map)
+            }
+        }
+    }
+
+    fn with_agent<AT: AgentTrait + 'static>(&self, agent: AT, map: &Map) -> Box<dyn Robotize> {
+        let camera = &self.camera;
+        match camera.kind {
+            Kind::Ideal => {
+                let sensor =
+                    IdealCamera::new(map.clone(), camera.distance_range, camera.direction_range);
+                self.with_sensor(agent, sensor)
+            }
+            Kind::Noisy => self.with_sensor(agent, camera.noisy(map)),
+        }
+    }
+
+    fn with_sensor<AT: AgentTrait + 'static, OS: OpticalSensor + 'static>(
+        &self,
+        agent: AT,
+        sensor: OS,
+    ) -> Box<dyn Robotize> {
+        let color = RGBColor(self.color.0, self.color.1, self.color.2);
+        if self.kind == Kind::Ideal {
+            let robot = IdealRobot::new(self.pose, &color, agent, sensor).set_radius(self.radius);
+            return Box::new(robot);
+        }
+
+        let mut robot = Robot::new(self.pose, &color, agent, sensor).set_radius(self.radius);
+        if let Some((noise_per_meter, noise_std)) = self.noise {
+            robot = robot.set_noise(noise_per_meter, noise_std);
+        }
+        if let Some(stds) = self.bias {
+            robot = robot.set_bias(stds);
+        }
+        if let Some((stuck, escape)) = self.stuck {
+            robot = robot.set_stuck(stuck, escape);
+        }
+        if let Some(k) = &self.kidnap {
+            robot = robot.set_kidnap(k.expected_time, k.range_x, k.range_y);
+        }
+        Box::new(robot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_defaults() {
+        let scenario: Scenario = r#"
+            [world]
+            time_span = 1.0
+            time_interval = 0.1
+
+            [[robots]]
+            pose = [0.0, 0.0, 0.0]
+            agent = { nu = 0.2, omega = 0.0 }
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(scenario.world.xlim, 5);
+        assert_eq!(scenario.world.seed, None);
+        assert!(scenario.landmarks.is_empty());
+        let robot = &scenario.robots[0];
+        assert_eq!(robot.kind, Kind::Ideal);
+        assert_eq!(robot.camera.kind, Kind::Ideal);
+        assert_eq!(robot.camera.distance_range, (0.5, 6.0));
+        assert!(robot.agent.estimator.is_none());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let missing_world = "landmarks = [[1.0, 2.0]]".parse::<Scenario>();
+        assert!(matches!(missing_world, Err(ScenarioError::Parse(_))));
+        let missing_file = Scenario::load("no/such/scenario.toml");
+        assert!(matches!(missing_file, Err(ScenarioError::Io(_))));
+    }
+
+    #[test]
+    fn test_build_bundled_scenarios() {
+        for text in &[
+            include_str!("../scenarios/mcl.toml"),
+            include_str!("../scenarios/multirobot.toml"),
+            include_str!("../scenarios/room.toml"),
+        ] {
+            let scenario: Scenario = text.parse().unwrap();
+            let mut world = scenario.build();
+            assert_eq!(world.objects().len(), scenario.robots.len());
+            assert_eq!(world.map.landmarks.len(), scenario.landmarks.len());
+            assert_eq!(world.map.walls.len(), scenario.walls.len());
+            assert_eq!(world.map.obstacles.len(), scenario.obstacles.len());
+
+            world.time_span = 1.0;
+            world.run();
+            assert!(world.objects().iter().all(|o| o.poses().len() == 11));
+        }
+    }
+
+    #[test]
+    fn test_seed_makes_scenario_reproducible() {
+        let text = include_str!("../scenarios/mcl.toml");
+        let run = || {
+            let mut world = text.parse::<Scenario>().unwrap().build();
+            world.time_span = 2.0;
+            world.run();
+            world.objects()[0].poses()
+        };
+        assert_eq!(run(), run());
+    }
+}
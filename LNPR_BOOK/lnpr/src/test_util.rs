@@ -0,0 +1,22 @@
+use ndarray::{arr2, Array2};
+
+use crate::base::*;
+
+// Motion noise (nn, nr, rn, rr) used by the estimators in the tests
+pub(crate) fn motion_noise_cov() -> Array2<f64> {
+    arr2(&[
+        [0.19f64.powi(2), 0.0, 0.0, 0.0],
+        [0.0, 0.001f64.powi(2), 0.0, 0.0],
+        [0.0, 0.0, 0.13f64.powi(2), 0.0],
+        [0.0, 0.0, 0.0, 0.2f64.powi(2)],
+    ])
+}
+
+// Three landmarks around the origin
+pub(crate) fn landmarks() -> Map {
+    let mut map = Map::new();
+    for ln in &[(-4.0, 2.0), (2.0, -3.0), (3.0, 3.0)] {
+        map.append_landmark(*ln);
+    }
+    map
+}
@@ -9,13 +9,13 @@ struct EstimateAgent {
 }
 
 impl AgentTrait for EstimateAgent {
-    fn decision(&mut self, _obs: &Vec<(f32, f32)>) -> (f32, f32) {
+    fn decision(&mut self, _obs: &Vec<Observation>) -> (f32, f32) {
         (self.nu, self.omega)
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        _drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        _drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         _xlim: i32,
         _ylim: i32,
     ) {
@@ -38,8 +38,7 @@ fn main() {
         .set_noise(0.0, 0.0)
         .set_bias(0.0, 0.0)
         .set_phantom(0.0, (-5.0, 5.0), (-5.0, 5.0))
-        .set_oversight(0.0)
-        .set_occlusion(0.0);
+        .set_oversight(0.0);
 
     let robot = Robot::new(
         (2.0, 2.0, PI / 6.0),
@@ -52,7 +51,7 @@ fn main() {
     .set_stuck(f32::INFINITY, 1e-100)
     .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
 
-    world.objects.push(Box::new(robot));
+    world.push(Box::new(robot));
     let root = BitMapBackend::gif("world.gif", (500, 500), 100)
         .unwrap()
         .into_drawing_area();
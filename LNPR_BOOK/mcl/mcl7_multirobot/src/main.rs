@@ -29,7 +29,7 @@ impl EstimateAgent {
 }
 
 impl AgentTrait for EstimateAgent {
-    fn decision(&mut self, _obs: &Vec<(f32, f32)>) -> (f32, f32) {
+    fn decision(&mut self, _obs: &Vec<Observation>) -> (f32, f32) {
         self.estimator
             .motion_update(self.prev_nu, self.prev_omega, self.time_interval);
         self.prev_nu = self.nu;
@@ -37,9 +37,9 @@ impl AgentTrait for EstimateAgent {
         (self.nu, self.omega)
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
@@ -67,7 +67,7 @@ impl Particle {
         let noised_omega = omega
             + ns[2] as f32 * (nu.abs() / time).sqrt()
             + ns[3] as f32 * (omega.abs() / time).sqrt();
-        self.pose = IdealRobot::<Agent, IdealCamera, RGBColor>::state_transition(
+        self.pose = IdealRobot::<Agent, IdealCamera>::state_transition(
             noised_nu,
             noised_omega,
             time,
@@ -102,9 +102,9 @@ impl Mcl {
         });
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
@@ -131,6 +131,16 @@ fn main() {
     }
 
     let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1);
+    let initial_pose = (2.0, 2.0, PI / 6.0);
+    let cov = arr2(&[
+        [0.18462f64.powi(2), 0.0, 0.0, 0.0],
+        [0.0, 0.001f64.powi(2), 0.0, 0.0],
+        [0.0, 0.0, 0.02264f64.powi(2), 0.0],
+        [0.0, 0.0, 0.0, 0.018462f64.powi(2)],
+    ]);
+    let mut estimator = Mcl::new(initial_pose, 100, cov);
+    estimator.motion_update(0.2, 10.0 / 180.0 * PI, 0.1);
+    let circle = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, estimator);
     let circle_no_estimate = Agent {
         nu: 0.2,
         omega: 10.0 / 180.0 * PI,
@@ -140,8 +150,21 @@ fn main() {
         .set_noise(0.0, 0.0)
         .set_bias(0.0, 0.0)
         .set_phantom(0.0, (-5.0, 5.0), (-5.0, 5.0))
-        .set_oversight(0.0)
-        .set_occlusion(0.0);
+        .set_oversight(0.0);
+
+    // The particles of the estimating robot are compared with the spread of
+    // 100 robots moving without estimation
+    let robot = Robot::new(
+        initial_pose,
+        &RGBColor(100, 100, 100),
+        circle.clone(),
+        IdealCamera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0)),
+    )
+    .set_noise(5.0, PI / 60.0)
+    .set_bias((0.1, 0.1))
+    .set_stuck(f32::INFINITY, 1e-100)
+    .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
+    world.push(Box::new(robot));
 
     for _i in 0..100 {
         let robot = Robot::new(
@@ -154,7 +177,7 @@ fn main() {
         .set_bias((0.1, 0.1))
         .set_stuck(f32::INFINITY, 1e-100)
         .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
-        world.objects.push(Box::new(robot));
+        world.push(Box::new(robot));
     }
     let root = BitMapBackend::gif("world.gif", (500, 500), 100)
         .unwrap()
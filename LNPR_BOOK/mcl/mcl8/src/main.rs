@@ -29,7 +29,7 @@ impl EstimateAgent {
 }
 
 impl AgentTrait for EstimateAgent {
-    fn decision(&mut self, obs: &Vec<(f32, f32)>) -> (f32, f32) {
+    fn decision(&mut self, obs: &Vec<Observation>) -> (f32, f32) {
         self.estimator
             .motion_update(self.prev_nu, self.prev_omega, self.time_interval);
         self.prev_nu = self.nu;
@@ -38,9 +38,9 @@ impl AgentTrait for EstimateAgent {
         (self.nu, self.omega)
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
@@ -68,7 +68,7 @@ impl Particle {
         let noised_omega = omega
             + ns[2] as f32 * (nu.abs() / time).sqrt()
             + ns[3] as f32 * (omega.abs() / time).sqrt();
-        self.pose = IdealRobot::<Agent, IdealCamera, RGBColor>::state_transition(
+        self.pose = IdealRobot::<Agent, IdealCamera>::state_transition(
             noised_nu,
             noised_omega,
             time,
@@ -76,7 +76,7 @@ impl Particle {
         );
     }
 
-    fn observation_update(&self, observation: &Vec<(f32, f32)>) {
+    fn observation_update(&self, observation: &Vec<Observation>) {
         println!("{:?}", observation);
     }
 }
@@ -107,15 +107,15 @@ impl Mcl {
         });
     }
 
-    fn observation_update(&self, observation: &Vec<(f32, f32)>) {
+    fn observation_update(&self, observation: &Vec<Observation>) {
         self.particles.iter().for_each(|p| {
             p.observation_update(observation);
         })
     }
 
-    fn draw<X: Ranged, Y: Ranged>(
+    fn draw<DB: DrawingBackend, X: Ranged, Y: Ranged>(
         &self,
-        drawing_area: &DrawingArea<BitMapBackend, Cartesian2d<X, Y>>,
+        drawing_area: &DrawingArea<DB, Cartesian2d<X, Y>>,
         xlim: i32,
         ylim: i32,
     ) {
@@ -157,8 +157,7 @@ fn main() {
         .set_noise(0.0, 0.0)
         .set_bias(0.0, 0.0)
         .set_phantom(0.0, (-5.0, 5.0), (-5.0, 5.0))
-        .set_oversight(0.0)
-        .set_occlusion(0.0);
+        .set_oversight(0.0);
 
     let robot = Robot::new(
         (2.0, 2.0, PI / 6.0),
@@ -171,7 +170,7 @@ fn main() {
     .set_stuck(f32::INFINITY, 1e-100)
     .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
 
-    world.objects.push(Box::new(robot));
+    world.push(Box::new(robot));
     let root = BitMapBackend::gif("world.gif", (500, 500), 100)
         .unwrap()
         .into_drawing_area();
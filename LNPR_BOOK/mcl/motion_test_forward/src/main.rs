@@ -22,21 +22,18 @@ fn main() {
             .set_stuck(f32::INFINITY, 1e-100)
             .set_kidnap(f32::INFINITY, (-5.0, 5.0), (-5.0, 5.0));
 
-        world.objects.push(Box::new(robot));
+        world.push(Box::new(robot));
     }
 
-    let root = BitMapBackend::gif("world.gif", (500, 500), 100)
-        .unwrap()
-        .into_drawing_area();
-    world.draw(&root);
+    world.run();
 
     // Check the result position and angle
     let mut r = Vec::new();
     let mut theta = Vec::new();
 
     for i in 0..100 {
-        r.push((world.objects[i].pose().0.powi(2) + world.objects[i].pose().1.powi(2)).sqrt());
-        theta.push(world.objects[i].pose().2);
+        r.push((world.objects()[i].pose().0.powi(2) + world.objects()[i].pose().1.powi(2)).sqrt());
+        theta.push(world.objects()[i].pose().2);
     }
 
     let r_series = Series::new("r", r);
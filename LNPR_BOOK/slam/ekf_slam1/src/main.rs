@@ -0,0 +1,54 @@
+use lnpr::prelude::*;
+use plotters::prelude::*;
+use std::f32::consts::PI;
+
+use ndarray::arr2;
+
+fn main() {
+    let mut map = Map::new();
+    for ln in &[
+        (-4.0, 2.0),
+        (2.0, -3.0),
+        (3.0, 3.0),
+        (0.0, 4.0),
+        (1.0, 1.0),
+        (-3.0, -1.0),
+    ] {
+        map.append_landmark(*ln);
+    }
+
+    let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1);
+    let initial_pose = (0.0, 0.0, 0.0);
+    let cov = arr2(&[
+        [0.18462f64.powi(2), 0.0, 0.0, 0.0],
+        [0.0, 0.001f64.powi(2), 0.0, 0.0],
+        [0.0, 0.0, 0.02264f64.powi(2), 0.0],
+        [0.0, 0.0, 0.0, 0.018462f64.powi(2)],
+    ]);
+    let estimator = EkfSlam::new(initial_pose, cov);
+    let circle = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, estimator);
+
+    let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0));
+
+    let robot = Robot::new(
+        initial_pose,
+        &RGBColor(100, 100, 100),
+        circle.clone(),
+        camera.clone(),
+    );
+
+    world.push(Box::new(robot));
+    let root = BitMapBackend::gif("world.gif", (500, 500), 100)
+        .unwrap()
+        .into_drawing_area();
+    world.draw(&root);
+
+    let robot = world
+        .object::<Robot<EstimateAgent<EkfSlam>, Camera>>(0)
+        .unwrap();
+    let estimator = &robot.agent.estimator;
+    for l in estimator.map().landmarks.iter() {
+        println!("id: {} estimated: {:?}", l.id, l.position);
+    }
+    println!("map error: {:?}", estimator.map_error(&map));
+}
@@ -0,0 +1,95 @@
+use lnpr::prelude::*;
+use plotters::prelude::*;
+use std::f32::consts::PI;
+
+use ndarray::arr2;
+
+fn main() {
+    let mut map = Map::new();
+    for ln in &[
+        (-4.0, 2.0),
+        (2.0, -3.0),
+        (3.0, 3.0),
+        (0.0, 4.0),
+        (1.0, 1.0),
+        (-3.0, -1.0),
+    ] {
+        map.append_landmark(*ln);
+    }
+
+    let mut world = World::new(map.clone(), 5, 5, 30.0, 0.1);
+    let initial_pose = (0.0, 0.0, 0.0);
+    let cov = arr2(&[
+        [0.18462f64.powi(2), 0.0, 0.0, 0.0],
+        [0.0, 0.001f64.powi(2), 0.0, 0.0],
+        [0.0, 0.0, 0.02264f64.powi(2), 0.0],
+        [0.0, 0.0, 0.0, 0.018462f64.powi(2)],
+    ]);
+    let estimator = GraphSlam::new(initial_pose, cov);
+    let circle = EstimateAgent::new(0.2, 10.0 / 180.0 * PI, 0.1, estimator);
+
+    let camera = Camera::new(map.clone(), (0.5, 6.0), (-PI / 3.0, PI / 3.0));
+
+    let robot = Robot::new(
+        initial_pose,
+        &RGBColor(100, 100, 100),
+        circle.clone(),
+        camera.clone(),
+    );
+
+    world.push(Box::new(robot));
+    let root = BitMapBackend::gif("world.gif", (500, 500), 100)
+        .unwrap()
+        .into_drawing_area();
+    world.draw(&root);
+
+    let robot = world
+        .object::<Robot<EstimateAgent<GraphSlam>, Camera>>(0)
+        .unwrap();
+    let truth = robot.poses.clone();
+    let mut estimator = robot.agent.estimator.clone();
+    let odometry = estimator.odometry();
+    println!("error before: {}", estimator.error());
+    println!("error after: {}", estimator.optimize(30));
+    for l in estimator.map.landmarks.iter() {
+        println!("id: {} estimated: {:?}", l.id, l.position);
+    }
+    println!("map error: {:?}", estimator.map_error(&map));
+
+    draw_trajectories(
+        "trajectory.png",
+        &[
+            (&truth, &BLACK, "truth"),
+            (&odometry, &RED, "odometry"),
+            (&estimator.poses, &BLUE, "graph slam"),
+        ],
+    );
+}
+
+fn draw_trajectories(name: &str, trajectories: &[(&Vec<(f32, f32, f32)>, &RGBColor, &str)]) {
+    let root = BitMapBackend::new(name, (600, 600)).into_drawing_area();
+    root.fill(&WHITE).unwrap();
+
+    let mut chart = ChartBuilder::on(&root)
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .caption("trajectory", ("sans-serif", 20))
+        .build_cartesian_2d(-5f32..5f32, -5f32..5f32)
+        .unwrap();
+    chart.configure_mesh().disable_mesh().draw().unwrap();
+
+    for (poses, color, label) in trajectories.iter() {
+        let color = **color;
+        chart
+            .draw_series(LineSeries::new(poses.iter().map(|p| (p.0, p.1)), &color))
+            .unwrap()
+            .label(*label)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &color));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(&WHITE)
+        .border_style(&BLACK)
+        .draw()
+        .unwrap();
+}